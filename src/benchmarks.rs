@@ -0,0 +1,345 @@
+// Copyright 2019-2021 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarking
+//!
+//! Every benchmark sets up the worst case for its call: associations verify a real signature,
+//! payouts create the destination account and merges hit an existing destination, together with
+//! `MaxRelayAccountsPerAccount` relay accounts. Relay chain keys are sr25519 keys generated in the
+//! keystore, so the benchmarking externalities must have one registered.
+
+use crate::{
+	merkle, AccountsPayable, AssociatedRelayAccounts, AutoClaimAuthority, BalanceOf, Call,
+	ClaimedRelayChainIds, Config, ContributionsMerkleRoot, Initialized, InitializedRewardAmount,
+	Pallet, PendingRewardAddresses, RewardInfo, UnassociatedContributions,
+	UnassociatedEthereumContributions,
+};
+use frame_benchmarking::{account, benchmarks, impl_benchmark_test_suite, whitelisted_caller};
+use frame_support::traits::{Currency, EnsureOrigin, Get};
+use frame_system::RawOrigin;
+use sp_core::{crypto::KeyTypeId, ecdsa, sr25519, H160, H256};
+use sp_io::hashing::keccak_256;
+use sp_runtime::traits::{Saturating, Zero};
+use sp_runtime::MultiSignature;
+use sp_std::vec::Vec;
+
+const SEED: u32 = 0;
+const RELAY_KEY_TYPE: KeyTypeId = KeyTypeId(*b"crwd");
+
+/// A reward comfortably above the existential deposit.
+fn reward<T: Config>() -> BalanceOf<T> {
+	T::RewardCurrency::minimum_balance()
+		.max(1u32.into())
+		.saturating_mul(1_000u32.into())
+}
+
+fn fund_pot<T: Config>(amount: BalanceOf<T>) {
+	T::RewardCurrency::make_free_balance_be(&Pallet::<T>::account_id(), amount);
+}
+
+fn relay_key() -> sr25519::Public {
+	sp_io::crypto::sr25519_generate(RELAY_KEY_TYPE, None)
+}
+
+fn relay_signature<T: Config>(
+	public: &sr25519::Public,
+	reward_account: &T::AccountId,
+) -> MultiSignature {
+	let payload = Pallet::<T>::association_payload(reward_account);
+	sp_io::crypto::sr25519_sign(RELAY_KEY_TYPE, public, &payload)
+		.expect("the key was just generated in the keystore")
+		.into()
+}
+
+fn ethereum_secret() -> secp256k1::SecretKey {
+	secp256k1::SecretKey::parse(&keccak_256(b"crowdloan-rewards")).expect("valid secret key")
+}
+
+fn ethereum_address(secret: &secp256k1::SecretKey) -> H160 {
+	let public = secp256k1::PublicKey::from_secret_key(secret).serialize();
+	H160::from_slice(&keccak_256(&public[1..65])[12..])
+}
+
+fn ethereum_signature<T: Config>(
+	secret: &secp256k1::SecretKey,
+	reward_account: &T::AccountId,
+) -> ecdsa::Signature {
+	let message = Pallet::<T>::association_message(reward_account);
+	let digest = keccak_256(&Pallet::<T>::ethereum_signable_message(&message));
+	let (signature, recovery_id) = secp256k1::sign(&secp256k1::Message::parse(&digest), secret);
+	let mut raw = [0u8; 65];
+	raw[0..64].copy_from_slice(&signature.serialize()[..]);
+	raw[64] = recovery_id.serialize() + 27;
+	ecdsa::Signature::from_raw(raw)
+}
+
+/// Record that the contributions of `count` distinct relay accounts, numbered from `first`, are
+/// paid to `native_account`.
+fn associate_relay_accounts<T: Config>(native_account: &T::AccountId, first: u32, count: u32)
+where
+	T::RelayChainAccountId: From<[u8; 32]>,
+{
+	for index in first..first + count {
+		let mut raw = [0u8; 32];
+		raw[..4].copy_from_slice(&index.to_le_bytes());
+		let relay_account: T::RelayChainAccountId = raw.into();
+		ClaimedRelayChainIds::<T>::insert(&relay_account, Some(native_account));
+		AssociatedRelayAccounts::<T>::append(native_account, relay_account);
+	}
+}
+
+benchmarks! {
+	where_clause { where T::RelayChainAccountId: From<[u8; 32]> }
+
+	associate_native_identity {
+		let caller: T::AccountId = whitelisted_caller();
+		let reward_account: T::AccountId = account("reward", 0, SEED);
+		let public = relay_key();
+		let relay_account: T::RelayChainAccountId = public.0.into();
+		UnassociatedContributions::<T>::insert(&relay_account, RewardInfo::<T>::new(reward::<T>()));
+		let proof = relay_signature::<T>(&public, &reward_account);
+	}: _(RawOrigin::Signed(caller), reward_account.clone(), relay_account, proof)
+	verify {
+		assert!(AccountsPayable::<T>::contains_key(&reward_account));
+	}
+
+	associate_native_identity_unsigned {
+		let reward_account: T::AccountId = account("reward", 0, SEED);
+		let public = relay_key();
+		let relay_account: T::RelayChainAccountId = public.0.into();
+		UnassociatedContributions::<T>::insert(&relay_account, RewardInfo::<T>::new(reward::<T>()));
+		let proof = relay_signature::<T>(&public, &reward_account);
+	}: _(RawOrigin::None, reward_account.clone(), relay_account, proof)
+	verify {
+		assert!(AccountsPayable::<T>::contains_key(&reward_account));
+	}
+
+	associate_native_identity_with_proof {
+		let p in 0 .. merkle::MAX_PROOF_LENGTH as u32;
+
+		let reward_account: T::AccountId = account("reward", 0, SEED);
+		let public = relay_key();
+		let relay_account: T::RelayChainAccountId = public.0.into();
+		let reward = reward::<T>();
+		let merkle_proof: Vec<H256> = (0..p).map(|i| H256::repeat_byte(i as u8)).collect();
+		let leaf = merkle::leaf(&relay_account, &reward);
+		let root = merkle_proof.iter().fold(leaf, |node, sibling| merkle::node(node, *sibling));
+		ContributionsMerkleRoot::<T>::put(root);
+		let proof = relay_signature::<T>(&public, &reward_account);
+	}: _(RawOrigin::None, reward_account.clone(), relay_account, reward, proof, merkle_proof)
+	verify {
+		assert!(AccountsPayable::<T>::contains_key(&reward_account));
+	}
+
+	set_contributions_merkle_root {
+		let root = H256::repeat_byte(1);
+	}: _(RawOrigin::Root, root)
+	verify {
+		assert_eq!(ContributionsMerkleRoot::<T>::get(), Some(root));
+	}
+
+	associate_ethereum_identity {
+		let reward_account: T::AccountId = account("reward", 0, SEED);
+		let secret = ethereum_secret();
+		let ethereum_address = ethereum_address(&secret);
+		UnassociatedEthereumContributions::<T>::insert(
+			&ethereum_address,
+			RewardInfo::<T>::new(reward::<T>()),
+		);
+		let proof = ethereum_signature::<T>(&secret, &reward_account);
+	}: _(RawOrigin::None, reward_account.clone(), ethereum_address, proof)
+	verify {
+		assert!(AccountsPayable::<T>::contains_key(&reward_account));
+	}
+
+	show_me_the_money {
+		// The caller has no account yet, so the payout creates it
+		let caller: T::AccountId = whitelisted_caller();
+		let reward = reward::<T>();
+		AccountsPayable::<T>::insert(&caller, RewardInfo::<T>::new(reward));
+		Initialized::<T>::put(true);
+		fund_pot::<T>(reward.saturating_mul(2u32.into()));
+		let now = T::VestingPeriod::get() / 2u32.into();
+		frame_system::Pallet::<T>::set_block_number(now);
+	}: _(RawOrigin::Signed(caller.clone()))
+	verify {
+		assert_eq!(AccountsPayable::<T>::get(&caller).map(|info| info.last_paid), Some(now));
+	}
+
+	claim_for {
+		let caller: T::AccountId = whitelisted_caller();
+		// The beneficiary has no account yet, so the payout creates it
+		let beneficiary: T::AccountId = account("beneficiary", 0, SEED);
+		let reward = reward::<T>();
+		AccountsPayable::<T>::insert(&beneficiary, RewardInfo::<T>::new(reward));
+		Initialized::<T>::put(true);
+		fund_pot::<T>(reward.saturating_mul(2u32.into()));
+		let now = T::VestingPeriod::get() / 2u32.into();
+		frame_system::Pallet::<T>::set_block_number(now);
+	}: _(RawOrigin::Signed(caller), beneficiary.clone())
+	verify {
+		assert_eq!(AccountsPayable::<T>::get(&beneficiary).map(|info| info.last_paid), Some(now));
+	}
+
+	lock_payout {
+		// Extending the vesting schedule of an earlier payout is the most expensive path. Runtimes
+		// with `NoVestingSchedule` cannot lock anything, but they only pay in `PayoutMode::Claim`,
+		// which never charges this weight, so the result of the lock is ignored.
+		let payee: T::AccountId = account("payee", 0, SEED);
+		let reward = reward::<T>();
+		let _ = Pallet::<T>::lock_unvested(&payee, reward, Zero::zero());
+		let now = T::VestingPeriod::get() / 2u32.into();
+		frame_system::Pallet::<T>::set_block_number(now);
+	}: {
+		let _ = Pallet::<T>::lock_unvested(&payee, reward, now);
+	}
+
+	payout_batch {
+		let n in 1 .. T::MaxBatchPayouts::get();
+
+		let caller: T::AccountId = whitelisted_caller();
+		let reward = reward::<T>();
+		// None of the beneficiaries has an account yet, so every payout creates one
+		let beneficiaries: Vec<T::AccountId> =
+			(0..n).map(|i| account("beneficiary", i, SEED)).collect();
+		for beneficiary in beneficiaries.iter() {
+			AccountsPayable::<T>::insert(beneficiary, RewardInfo::<T>::new(reward));
+		}
+		Initialized::<T>::put(true);
+		fund_pot::<T>(reward.saturating_mul((n + 1).into()));
+		let now = T::VestingPeriod::get() / 2u32.into();
+		frame_system::Pallet::<T>::set_block_number(now);
+	}: _(RawOrigin::Signed(caller), beneficiaries.clone())
+	verify {
+		for beneficiary in beneficiaries.iter() {
+			assert_eq!(AccountsPayable::<T>::get(beneficiary).map(|info| info.last_paid), Some(now));
+		}
+	}
+
+	update_reward_address {
+		// Merging into an account that already has rewards is the most expensive path. Every
+		// relay account of the old account is moved to the new one, which has the rest.
+		let r in 0 .. T::MaxRelayAccountsPerAccount::get();
+		let caller: T::AccountId = whitelisted_caller();
+		let new_reward_account: T::AccountId = account("reward", 0, SEED);
+		AccountsPayable::<T>::insert(&caller, RewardInfo::<T>::new(reward::<T>()));
+		AccountsPayable::<T>::insert(&new_reward_account, RewardInfo::<T>::new(reward::<T>()));
+		associate_relay_accounts::<T>(&caller, 0, r);
+		associate_relay_accounts::<T>(
+			&new_reward_account,
+			r,
+			T::MaxRelayAccountsPerAccount::get() - r,
+		);
+	}: _(RawOrigin::Signed(caller.clone()), new_reward_account)
+	verify {
+		assert!(!AccountsPayable::<T>::contains_key(&caller));
+	}
+
+	propose_reward_address {
+		let caller: T::AccountId = whitelisted_caller();
+		let new_reward_account: T::AccountId = account("reward", 0, SEED);
+		AccountsPayable::<T>::insert(&caller, RewardInfo::<T>::new(reward::<T>()));
+	}: _(RawOrigin::Signed(caller.clone()), new_reward_account)
+	verify {
+		assert!(PendingRewardAddresses::<T>::contains_key(&caller));
+	}
+
+	accept_reward_address {
+		// Merging into an account that already has rewards is the most expensive path. Every
+		// relay account of the current account is moved to the caller, which has the rest.
+		let r in 0 .. T::MaxRelayAccountsPerAccount::get();
+		let caller: T::AccountId = whitelisted_caller();
+		let current_reward_account: T::AccountId = account("reward", 0, SEED);
+		AccountsPayable::<T>::insert(&current_reward_account, RewardInfo::<T>::new(reward::<T>()));
+		AccountsPayable::<T>::insert(&caller, RewardInfo::<T>::new(reward::<T>()));
+		associate_relay_accounts::<T>(&current_reward_account, 0, r);
+		associate_relay_accounts::<T>(&caller, r, T::MaxRelayAccountsPerAccount::get() - r);
+		Pallet::<T>::propose_reward_address(
+			RawOrigin::Signed(current_reward_account.clone()).into(),
+			caller.clone(),
+		)?;
+	}: _(RawOrigin::Signed(caller), current_reward_account.clone())
+	verify {
+		assert!(!AccountsPayable::<T>::contains_key(&current_reward_account));
+	}
+
+	cancel_reward_address {
+		let caller: T::AccountId = whitelisted_caller();
+		let new_reward_account: T::AccountId = account("reward", 0, SEED);
+		AccountsPayable::<T>::insert(&caller, RewardInfo::<T>::new(reward::<T>()));
+		Pallet::<T>::propose_reward_address(
+			RawOrigin::Signed(caller.clone()).into(),
+			new_reward_account,
+		)?;
+	}: _(RawOrigin::Signed(caller.clone()))
+	verify {
+		assert!(!PendingRewardAddresses::<T>::contains_key(&caller));
+	}
+
+	initialize_reward_vec {
+		let x in 1 .. T::MaxInitContributors::get();
+
+		Initialized::<T>::put(false);
+		InitializedRewardAmount::<T>::kill();
+		let reward = reward::<T>();
+		let rewards: Vec<(T::RelayChainAccountId, Option<T::AccountId>, BalanceOf<T>)> = (0..x)
+			.map(|i| {
+				let mut relay_account = [0u8; 32];
+				relay_account[..4].copy_from_slice(&i.to_le_bytes());
+				(relay_account.into(), Some(account("contributor", i, SEED)), reward)
+			})
+			.collect();
+		let origin = T::InitializationOrigin::successful_origin();
+	}: {
+		Pallet::<T>::initialize_reward_vec(origin, rewards)?;
+	}
+	verify {
+		assert_eq!(InitializedRewardAmount::<T>::get(), reward.saturating_mul(x.into()));
+	}
+
+	complete_initialization {
+		Initialized::<T>::put(false);
+		let reward = reward::<T>();
+		InitializedRewardAmount::<T>::put(reward);
+		let origin = T::InitializationOrigin::successful_origin();
+	}: {
+		Pallet::<T>::complete_initialization(origin, reward)?;
+	}
+	verify {
+		assert!(Initialized::<T>::get());
+	}
+
+	set_vesting_schedule {
+		Initialized::<T>::put(false);
+		let start = frame_system::Pallet::<T>::block_number() + 1u32.into();
+		let end = start + T::VestingPeriod::get() + 1u32.into();
+		let origin = T::InitializationOrigin::successful_origin();
+	}: {
+		Pallet::<T>::set_vesting_schedule(origin, start, end)?;
+	}
+	verify {
+		assert_eq!(Pallet::<T>::vesting_schedule(), (start, end));
+	}
+
+	set_auto_claim_authority {
+		let authority: T::AccountId = account("authority", 0, SEED);
+	}: _(RawOrigin::Root, Some(authority.clone()))
+	verify {
+		assert_eq!(AutoClaimAuthority::<T>::get(), Some(authority));
+	}
+}
+
+impl_benchmark_test_suite!(Pallet, crate::mock::benchmarks_ext(), crate::mock::Test);
@@ -0,0 +1,48 @@
+// Copyright 2019-2021 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The key the offchain worker signs automatic claims with
+//!
+//! Insert an sr25519 key of this type (`crwl`) into the keystore of the node that should submit
+//! automatic claims, and make its account the `AutoClaimAuthority`.
+
+use sp_core::crypto::KeyTypeId;
+use sp_core::sr25519::{Public as Sr25519Public, Signature as Sr25519Signature};
+use sp_runtime::app_crypto::{app_crypto, sr25519};
+use sp_runtime::traits::Verify;
+use sp_runtime::{MultiSignature, MultiSigner};
+
+/// The key type of the automatic claims authority.
+pub const KEY_TYPE: KeyTypeId = KeyTypeId(*b"crwl");
+
+app_crypto!(sr25519, KEY_TYPE);
+
+/// The `AuthorityId` to use in runtimes with `MultiSignature` accounts.
+pub struct AutoClaimAuthId;
+
+impl frame_system::offchain::AppCrypto<MultiSigner, MultiSignature> for AutoClaimAuthId {
+	type RuntimeAppPublic = Public;
+	type GenericSignature = Sr25519Signature;
+	type GenericPublic = Sr25519Public;
+}
+
+impl frame_system::offchain::AppCrypto<<Sr25519Signature as Verify>::Signer, Sr25519Signature>
+	for AutoClaimAuthId
+{
+	type RuntimeAppPublic = Public;
+	type GenericSignature = Sr25519Signature;
+	type GenericPublic = Sr25519Public;
+}
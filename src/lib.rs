@@ -25,18 +25,32 @@
 //! This pallet stores a constant  "reward ratio" which is the number of reward tokens to pay per
 //! contributed token. In simple cases this can be 1, but needs to be customizeable to allow for
 //! vastly differing absolute token supplies between relay and para.
-//! Vesting is also linear. No tokens are vested at genesis and they unlock linearly until a
-//! predecided block number. Vesting computations happen on demand when payouts are requested. So
-//! no block weight is ever wasted on this, and there is no "base-line" cost of updating vestings.
-//! Like I said, we can anything we want there. Even a non-linear reward curve to disincentivize
-//! whales.
+//! A configurable share of each reward (`InitialPayment`) can be claimed right away. The rest is
+//! not vested at genesis and unlocks until a predecided block number following a configurable
+//! `VestingCurve` (linear, cliff-then-linear, stepped or exponential decay). Vesting
+//! computations happen on demand when payouts are requested. So no block weight is ever wasted on
+//! this, and there is no "base-line" cost of updating vestings.
+//! Like I said, we can anything we want there. A non-linear reward curve to disincentivize
+//! whales is just another `VestingCurve` implementation.
+//!
+//! Rewards are never minted. They are paid out of a pot controlled by the pallet, whose account
+//! is derived from the configured `PalletId`. The pot is funded at genesis or by a regular
+//! transfer, which keeps total issuance auditable and caps what the crowdloan can ever cost.
 //!
 //! ## Payout Mechanism
 //!
 //! The current payout mechanism requires contributors to claim their payouts. Because they are
 //! paying the transaction fees for this themselves, they can do it as often as every block, or
-//! wait and claim the entire thing once it is fully vested. We could consider auto payouts if we
-//! want.
+//! wait and claim the entire thing once it is fully vested. Anyone can also trigger the payout of
+//! a contributor with `claim_for`, which lets third parties run auto payouts. Chains can opt into
+//! automatic payouts in `on_initialize`, which pays a bounded number of accounts every block.
+//! Alternatively, an offchain worker can look for claimable rewards and submit them for payout
+//! with unsigned `auto_claim` transactions, signed by a configured authority key. That moves the
+//! cost of iterating over contributors off-chain.
+//!
+//! Chains can instead configure `PayoutMode::Lock`. The first payout then transfers the whole
+//! reward, and whatever is not vested yet is locked through the runtime's `VestingSchedule` (eg
+//! `pallet_vesting`), so contributors can vote or stake with their full reward right away.
 //!
 //! ## Sourcing Contribution Information
 //!
@@ -56,6 +70,26 @@
 //! contributor is responsible for making a transaction that associates a native ID. The tx
 //! includes a signature by the relay chain identity over the native identity.
 //!
+//! * **Unassociated Ethereum contributions at Genesis**
+//!
+//! Contributions made offchain from Ethereum wallets are configured at genesis by their H160
+//! address. The contributor associates a native ID by submitting an `eth_sign` (EIP-191)
+//! signature over the same association message, so no Substrate keys are needed.
+//!
+//! * **Merkle root of contributions**
+//!
+//! Enumerating every contributor in genesis bloats the genesis state of large crowdloans. Instead
+//! genesis (or root) can store only the root of a Merkle tree of `(relay account, reward)`
+//! leaves. The contributor then provides its Merkle proof together with the usual signature, and
+//! the contribution is written to storage on that first claim.
+//!
+//! * **Initialized after genesis**
+//!
+//! A parachain that wins a slot after launch registers contributions in chunks through
+//! `initialize_reward_vec`, from a configurable origin. Each entry may or may not already be
+//! associated with a native account. `complete_initialization` then checks the total against the
+//! expected sum and locks the set. No rewards are paid until the set is complete.
+//!
 //! * **ReadingRelayState**
 //!
 //! The most elegant, but most complex solution would be for the para to read the contributions
@@ -70,31 +104,70 @@
 
 use frame_support::pallet;
 pub use pallet::*;
+pub use vesting::{PayoutMode, VestingCurve};
+pub use weights::WeightInfo;
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarks;
+pub mod crypto;
+pub mod merkle;
+pub mod migrations;
 #[cfg(test)]
 pub(crate) mod mock;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod runtime_api;
 #[cfg(test)]
 mod tests;
+pub mod vesting;
+pub mod weights;
 
 #[pallet]
 pub mod pallet {
 
+	use crate::merkle;
+	use crate::migrations;
+	use crate::vesting::{PayoutMode, VestingCurve};
+	use crate::weights::WeightInfo;
 	use frame_support::dispatch::fmt::Debug;
 	use frame_support::pallet_prelude::*;
-	use frame_support::traits::Currency;
+	use frame_support::storage::StoragePrefixedMap;
+	use frame_support::traits::{
+		Currency, ExistenceRequirement::AllowDeath, PalletInfo as _, VestingSchedule,
+	};
+	use frame_support::{transactional, PalletId};
+	use frame_system::offchain::{
+		AppCrypto, SendTransactionTypes, SendUnsignedTransaction, SignedPayload, Signer,
+		SigningTypes,
+	};
 	use frame_system::pallet_prelude::*;
 	use log::warn;
 	use sp_core::crypto::AccountId32;
-	use sp_runtime::traits::Saturating;
+	use sp_core::{ecdsa, H160, H256};
+	use sp_io::hashing::keccak_256;
+	use sp_runtime::helpers_128bit::multiply_by_rational;
 	use sp_runtime::traits::Verify;
-	use sp_runtime::{MultiSignature, SaturatedConversion};
-	use sp_std::convert::TryInto;
+	use sp_runtime::traits::{AccountIdConversion, IdentifyAccount};
+	use sp_runtime::traits::{One, Saturating, Zero};
+	use sp_runtime::transaction_validity::{
+		InvalidTransaction, TransactionLongevity, TransactionPriority, TransactionSource,
+		TransactionValidity, ValidTransaction,
+	};
+	use sp_runtime::{FixedPointNumber, FixedU128, MultiSignature, Perbill, SaturatedConversion};
+	use sp_std::collections::{btree_map::BTreeMap, btree_set::BTreeSet};
+	use sp_std::convert::TryFrom;
+	use sp_std::vec::Vec;
+	pub const WRAPPED_BYTES_PREFIX: &[u8] = b"<Bytes>";
+	pub const WRAPPED_BYTES_POSTFIX: &[u8] = b"</Bytes>";
+
 	/// The Author Filter pallet
 	#[pallet::pallet]
 	pub struct Pallet<T>(PhantomData<T>);
 
 	/// Configuration trait of this pallet.
 	#[pallet::config]
-	pub trait Config: frame_system::Config {
+	pub trait Config:
+		frame_system::Config + SendTransactionTypes<Call<Self>> + SigningTypes
+	{
 		/// The overarching event type
 		type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
 		/// The currency in which the rewards will be paid (probably the parachain native currency)
@@ -111,51 +184,317 @@ pub mod pallet {
 			+ Debug
 			+ Into<AccountId32>;
 
-		/// The total vesting period.
+		/// The length of the vesting period, when no end block is configured at genesis or
+		/// through `set_vesting_schedule`.
 		type VestingPeriod: Get<Self::BlockNumber>;
+
+		/// A prefix identifying this network in the association payload, eg `b"moonbeam-"`.
+		/// Together with the genesis hash it prevents association signatures from being replayed
+		/// on other chains.
+		type SignatureNetworkIdentifier: Get<&'static [u8]>;
+
+		/// The priority of unsigned association transactions in the pool.
+		type UnsignedPriority: Get<TransactionPriority>;
+
+		/// How rewards unlock over the vesting period. The `vesting` module provides linear,
+		/// cliff-then-linear, stepped and exponential-decay curves.
+		type VestingCurve: VestingCurve<BalanceOf<Self>, Self::BlockNumber>;
+
+		/// The origin allowed to register contributions after genesis.
+		type InitializationOrigin: EnsureOrigin<Self::Origin>;
+
+		/// The maximum number of contributions registered by a single `initialize_reward_vec`.
+		type MaxInitContributors: Get<u32>;
+
+		/// The share of each reward that is not subject to vesting and can be claimed right away.
+		/// The rest of the reward follows `VestingCurve`.
+		type InitialPayment: Get<Perbill>;
+
+		/// The id from which the account holding the reward funds is derived. The pot must be
+		/// funded (at genesis or by a regular transfer) before any rewards can be paid.
+		type PalletId: Get<PalletId>;
+
+		/// The maximum number of beneficiaries paid by a single `payout_batch`.
+		type MaxBatchPayouts: Get<u32>;
+
+		/// How many accounts `on_initialize` visits, and pays if anything is claimable, every
+		/// block. Zero disables automatic payouts.
+		type AutoPayoutsPerBlock: Get<u32>;
+
+		/// The maximum weight `on_initialize` may spend on automatic payouts every block.
+		type AutoPayoutWeightBudget: Get<Weight>;
+
+		/// The key the offchain worker signs `auto_claim` payloads with, eg
+		/// `crypto::AutoClaimAuthId`. Only payloads signed by `AutoClaimAuthority` are accepted.
+		type AuthorityId: AppCrypto<Self::Public, Self::Signature>;
+
+		/// The offchain worker only submits accounts that could claim at least this much, so that
+		/// automatic claims are not wasted on dust.
+		type AutoClaimThreshold: Get<BalanceOf<Self>>;
+
+		/// Whether contributors claim vested rewards as they go, or get the whole reward at once
+		/// with the unvested part locked by `VestingSchedule`.
+		type PayoutMode: Get<PayoutMode>;
+
+		/// Locks the unvested part of rewards in `PayoutMode::Lock`, eg `pallet_vesting`. Runtimes
+		/// that only use `PayoutMode::Claim` can use `vesting::NoVestingSchedule`.
+		type VestingSchedule: VestingSchedule<
+			Self::AccountId,
+			Moment = Self::BlockNumber,
+			Currency = Self::RewardCurrency,
+		>;
+
+		/// How many blocks the proposed account has to accept a `propose_reward_address`.
+		type RewardAddressProposalExpiry: Get<Self::BlockNumber>;
+
+		/// The maximum number of relay accounts and Ethereum addresses whose contributions are
+		/// paid to the same native account. Moving a reward to another account visits each of
+		/// them.
+		type MaxRelayAccountsPerAccount: Get<u32>;
+
+		/// Weight information for the extrinsics in this pallet. The shipped `SubstrateWeight`
+		/// values are estimates; runtimes should use weights generated from the benchmarks.
+		type WeightInfo: WeightInfo;
 	}
 
-	type BalanceOf<T> = <<T as Config>::RewardCurrency as Currency<
+	pub type BalanceOf<T> = <<T as Config>::RewardCurrency as Currency<
 		<T as frame_system::Config>::AccountId,
 	>>::Balance;
 	/// Stores info about the rewards owed as well as how much has been vested so far.
 	/// For a primer on this kind of design, see the recipe on compounding interest
 	/// https://substrate.dev/recipes/fixed-point.html#continuously-compounding
 	#[derive(Default, Clone, Encode, Decode, RuntimeDebug)]
+	#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
+	#[cfg_attr(feature = "std", serde(bound = ""))]
+	#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
 	pub struct RewardInfo<T: Config> {
 		pub total_reward: BalanceOf<T>,
 		pub claimed_reward: BalanceOf<T>,
 		pub last_paid: T::BlockNumber,
+		/// The upfront tranche of `total_reward`, which is released by the first claim.
+		pub initial_payment: BalanceOf<T>,
+	}
+
+	impl<T: Config> RewardInfo<T> {
+		/// A fresh, unclaimed reward with the configured initial payment split out of it.
+		pub fn new(total_reward: BalanceOf<T>) -> Self {
+			RewardInfo {
+				total_reward,
+				claimed_reward: 0u32.into(),
+				last_paid: 0u32.into(),
+				initial_payment: T::InitialPayment::get().mul_floor(total_reward),
+			}
+		}
+
+		/// Fold `other` into this reward, eg when two rewards end up owed to the same account.
+		///
+		/// Every reward follows the same vesting schedule, and vesting curves scale with the
+		/// reward. So summing the totals, claims and initial payments vests, at any block, what
+		/// both rewards would have vested separately (rounding can only add up to one planck per
+		/// merge, never lose one). Neither reward loses its claims nor its vesting progress.
+		pub fn merge(&mut self, other: RewardInfo<T>) {
+			self.total_reward = self.total_reward.saturating_add(other.total_reward);
+			self.claimed_reward = self.claimed_reward.saturating_add(other.claimed_reward);
+			self.initial_payment = self.initial_payment.saturating_add(other.initial_payment);
+			self.last_paid = self.last_paid.max(other.last_paid);
+		}
+	}
+
+	/// The accounts the offchain worker of the auto claim authority wants paid, as of
+	/// `block_number`.
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+	pub struct AutoClaimPayload<Public, AccountId, BlockNumber> {
+		pub beneficiaries: Vec<AccountId>,
+		pub block_number: BlockNumber,
+		pub public: Public,
+	}
+
+	impl<T: SigningTypes> SignedPayload<T>
+		for AutoClaimPayload<T::Public, T::AccountId, T::BlockNumber>
+	{
+		fn public(&self) -> T::Public {
+			self.public.clone()
+		}
+	}
+
+	/// A reward address change waiting for the new account to accept it.
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+	pub struct PendingRewardAddress<AccountId, BlockNumber> {
+		pub new_reward_account: AccountId,
+		/// The last block at which the change can be accepted.
+		pub expires_at: BlockNumber,
+	}
+
+	/// The layout of the pallet storage. See the `migrations` module.
+	#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, RuntimeDebug)]
+	pub enum Releases {
+		/// `RewardInfo` without `initial_payment`, and no `Initialized` flag.
+		V1_0_0,
+		/// `RewardInfo` with `initial_payment`.
+		V2_0_0,
+		/// `ClaimedRelayChainIds` and `ClaimedEthereumAddresses` holding the associated native
+		/// account, indexed the other way around by `AssociatedRelayAccounts` and
+		/// `AssociatedEthereumAddresses`.
+		V3_0_0,
+	}
+
+	impl Default for Releases {
+		fn default() -> Self {
+			// Chains that predate storage versioning
+			Releases::V1_0_0
+		}
+	}
+
+	impl Releases {
+		/// The layout of the current code, which fresh chains start with.
+		pub const LATEST: Releases = Releases::V3_0_0;
 	}
 
-	// No hooks
 	#[pallet::hooks]
-	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {}
+	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+		fn on_initialize(_n: T::BlockNumber) -> Weight {
+			let max_payouts = T::AutoPayoutsPerBlock::get();
+			if max_payouts.is_zero() {
+				return 0;
+			}
+			Self::auto_payout(max_payouts, T::AutoPayoutWeightBudget::get())
+		}
+
+		fn offchain_worker(now: T::BlockNumber) {
+			if let Err(error) = Self::submit_auto_claim(now) {
+				log::debug!("Rewards: no automatic claim submitted: {}", error);
+			}
+		}
+
+		fn on_runtime_upgrade() -> Weight {
+			let mut weight = T::DbWeight::get().reads(1);
+			let version = StorageVersion::<T>::get();
+			if version < Releases::V2_0_0 {
+				weight = weight.saturating_add(migrations::v2::migrate::<T>());
+			}
+			if version < Releases::V3_0_0 {
+				weight = weight.saturating_add(migrations::v3::migrate::<T>());
+			}
+			weight
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<(), &'static str> {
+			if StorageVersion::<T>::get() == Releases::V1_0_0 {
+				migrations::v2::pre_upgrade::<T>()?;
+			}
+			Ok(())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade() -> Result<(), &'static str> {
+			frame_support::ensure!(
+				StorageVersion::<T>::get() == Releases::LATEST,
+				"Rewards: storage is not at the latest version"
+			);
+			migrations::v2::post_upgrade::<T>()?;
+			migrations::v3::post_upgrade::<T>()
+		}
+	}
 
-	#[pallet::call]
 	impl<T: Config> Pallet<T> {
-		/// Associate a native rewards_destination identity with a crowdloan contribution.
-		///
-		/// This is an unsigned call because the caller may not have any funds to pay fees with.
-		/// This is inspired by Polkadot's claims pallet:
-		/// https://github.com/paritytech/polkadot/blob/master/runtime/common/src/claims.rs
+		/// The account that holds the reward pot. All payouts are transferred from here, so the
+		/// total cost of the crowdloan is bounded by whatever was put into this account.
+		pub fn account_id() -> T::AccountId {
+			T::PalletId::get().into_account()
+		}
+
+		/// The message a contributor must sign to associate `reward_account` with its
+		/// contribution.
 		///
-		/// This function and the entire concept of unassociated contributions may be obviated if
-		/// They will accept a memo filed in the Polkadot crowdloan pallet.
-		#[pallet::weight(0)]
-		pub fn associate_native_identity(
-			origin: OriginFor<T>,
-			reward_account: T::AccountId,
-			relay_account: T::RelayChainAccountId,
-			proof: MultiSignature,
-		) -> DispatchResultWithPostInfo {
-			ensure_signed(origin)?;
+		/// The reward account alone could have been signed for any other purpose, and the same
+		/// signature would be valid on every chain running this pallet. So the message is bound
+		/// to this network, this chain's genesis hash and this pallet's name.
+		pub fn association_message(reward_account: &T::AccountId) -> Vec<u8> {
+			let genesis_hash = frame_system::Pallet::<T>::block_hash(T::BlockNumber::zero());
+			let pallet_name =
+				<T as frame_system::Config>::PalletInfo::name::<Self>().unwrap_or_default();
+
+			let mut message = T::SignatureNetworkIdentifier::get().to_vec();
+			message.extend_from_slice(genesis_hash.as_ref());
+			message.extend_from_slice(pallet_name.as_bytes());
+			message.extend_from_slice(&reward_account.encode());
+			message
+		}
+
+		/// The payload relay chain accounts sign: the association message wrapped in `<Bytes>`
+		/// tags, which is what polkadot-js does when signing raw bytes.
+		pub fn association_payload(reward_account: &T::AccountId) -> Vec<u8> {
+			let mut payload = WRAPPED_BYTES_PREFIX.to_vec();
+			payload.extend(Self::association_message(reward_account));
+			payload.extend_from_slice(WRAPPED_BYTES_POSTFIX);
+			payload
+		}
+
+		/// The EIP-191 message an Ethereum wallet signs when asked to `eth_sign` (or
+		/// `personal_sign`) `message`.
+		pub fn ethereum_signable_message(message: &[u8]) -> Vec<u8> {
+			let mut length = message.len();
+			let mut length_digits = Vec::new();
+			while length > 0 {
+				length_digits.push(b'0' + (length % 10) as u8);
+				length /= 10;
+			}
+			let mut signable = b"\x19Ethereum Signed Message:\n".to_vec();
+			signable.extend(length_digits.into_iter().rev());
+			signable.extend_from_slice(message);
+			signable
+		}
+
+		/// Recover the Ethereum address that `eth_sign`ed `message`, if any.
+		fn ethereum_recover(signature: &ecdsa::Signature, message: &[u8]) -> Option<H160> {
+			let digest = keccak_256(&Self::ethereum_signable_message(message));
+			let public =
+				sp_io::crypto::secp256k1_ecdsa_recover(signature.as_ref(), &digest).ok()?;
+			Some(H160::from_slice(&keccak_256(&public)[12..]))
+		}
+
+		/// Check that `proof` is an `eth_sign` signature of the association message for
+		/// `reward_account` by `ethereum_address` and that the address has a contribution waiting
+		/// to be associated, which is returned.
+		fn check_ethereum_association(
+			reward_account: &T::AccountId,
+			ethereum_address: &H160,
+			proof: &ecdsa::Signature,
+		) -> Result<RewardInfo<T>, Error<T>> {
+			let message = Self::association_message(reward_account);
+			ensure!(
+				Self::ethereum_recover(proof, &message).as_ref() == Some(ethereum_address),
+				Error::<T>::InvalidClaimSignature
+			);
+
+			// We ensure the address was not yet associated to avoid multi-claiming
+			ensure!(
+				!ClaimedEthereumAddresses::<T>::contains_key(ethereum_address),
+				Error::<T>::AlreadyAssociated
+			);
+			ensure!(
+				Self::associations_of(reward_account) < T::MaxRelayAccountsPerAccount::get(),
+				Error::<T>::TooManyRelayAccounts
+			);
+
+			UnassociatedEthereumContributions::<T>::get(ethereum_address)
+				.ok_or(Error::<T>::NoAssociatedClaim)
+		}
+
+		/// Check that `proof` is a signature of `reward_account` by `relay_account` and that the
+		/// relay account was not associated yet.
+		fn check_relay_signature(
+			reward_account: &T::AccountId,
+			relay_account: &T::RelayChainAccountId,
+			proof: &MultiSignature,
+		) -> Result<(), Error<T>> {
 			// Check the proof:
 			// 1. Is signed by an actual unassociated contributor
 			// 2. Signs a valid native identity
-			// Check the proof. The Proof consists of a Signature of the rewarded account with the
-			// claimer key
-			let payload = reward_account.encode();
+			// Check the proof. The Proof consists of a Signature of the association payload for
+			// the rewarded account with the claimer key
+			let payload = Self::association_payload(reward_account);
 			ensure!(
 				proof.verify(payload.as_slice(), &relay_account.clone().into()),
 				Error::<T>::InvalidClaimSignature
@@ -163,22 +502,66 @@ pub mod pallet {
 
 			// We ensure the relay chain id wast not yet associated to avoid multi-claiming
 			ensure!(
-				ClaimedRelayChainIds::<T>::get(&relay_account).is_none(),
+				!ClaimedRelayChainIds::<T>::contains_key(relay_account),
 				Error::<T>::AlreadyAssociated
 			);
+			ensure!(
+				Self::associations_of(reward_account) < T::MaxRelayAccountsPerAccount::get(),
+				Error::<T>::TooManyRelayAccounts
+			);
+
+			Ok(())
+		}
+
+		/// Check that `proof` is a signature of `reward_account` by `relay_account` and that the
+		/// relay account has a contribution waiting to be associated, which is returned.
+		fn check_association(
+			reward_account: &T::AccountId,
+			relay_account: &T::RelayChainAccountId,
+			proof: &MultiSignature,
+		) -> Result<RewardInfo<T>, Error<T>> {
+			Self::check_relay_signature(reward_account, relay_account, proof)?;
 
 			// Upon error this should check the relay chain state in this case
-			let reward_info = UnassociatedContributions::<T>::get(&relay_account)
-				.ok_or(Error::<T>::NoAssociatedClaim)?;
+			UnassociatedContributions::<T>::get(relay_account).ok_or(Error::<T>::NoAssociatedClaim)
+		}
+
+		/// Check that `proof` is a signature of `reward_account` by `relay_account` and that
+		/// `merkle_proof` shows `reward` is owed to the relay account under the contributions
+		/// Merkle root. Returns the reward info to insert on first claim.
+		fn check_merkle_association(
+			reward_account: &T::AccountId,
+			relay_account: &T::RelayChainAccountId,
+			reward: BalanceOf<T>,
+			proof: &MultiSignature,
+			merkle_proof: &[H256],
+		) -> Result<RewardInfo<T>, Error<T>> {
+			Self::check_relay_signature(reward_account, relay_account, proof)?;
+
+			let root = ContributionsMerkleRoot::<T>::get().ok_or(Error::<T>::NoAssociatedClaim)?;
+			ensure!(
+				merkle::verify_proof(root, merkle::leaf(relay_account, &reward), merkle_proof),
+				Error::<T>::InvalidMerkleProof
+			);
+
+			Ok(RewardInfo::new(reward))
+		}
 
+		/// Move an already checked contribution from unassociated to payable. It adds up with the
+		/// contributions already associated with `reward_account`, if any.
+		fn do_associate(
+			reward_account: T::AccountId,
+			relay_account: T::RelayChainAccountId,
+			reward_info: RewardInfo<T>,
+		) {
 			// Insert on payable
-			AccountsPayable::<T>::insert(&reward_account, &reward_info);
+			Self::add_reward(&reward_account, reward_info.clone());
 
 			// Remove from unassociated
 			<UnassociatedContributions<T>>::remove(&relay_account);
 
 			// Insert in mapping
-			ClaimedRelayChainIds::<T>::insert(&relay_account, ());
+			Self::record_association(&relay_account, &reward_account);
 
 			// Emit Event
 			Self::deposit_event(Event::NativeIdentityAssociated(
@@ -186,95 +569,980 @@ pub mod pallet {
 				reward_account,
 				reward_info.total_reward,
 			));
+		}
 
-			Ok(Default::default())
+		/// Make `reward_info` payable to `account`. If the account is already owed rewards, eg for
+		/// a contribution from another relay account, the two are merged with `RewardInfo::merge`.
+		fn add_reward(account: &T::AccountId, reward_info: RewardInfo<T>) {
+			AccountsPayable::<T>::mutate(account, |existing| match existing {
+				Some(info) => info.merge(reward_info),
+				None => *existing = Some(reward_info),
+			});
 		}
 
-		/// Collect whatever portion of your reward are currently vested.
-		#[pallet::weight(0)]
-		pub fn show_me_the_money(origin: OriginFor<T>) -> DispatchResultWithPostInfo {
-			let payee = ensure_signed(origin)?;
+		/// How many relay accounts and Ethereum addresses have their contributions paid to
+		/// `native_account`.
+		fn associations_of(native_account: &T::AccountId) -> u32 {
+			let relay_accounts = AssociatedRelayAccounts::<T>::decode_len(native_account);
+			let ethereum_addresses = AssociatedEthereumAddresses::<T>::decode_len(native_account);
+			(relay_accounts.unwrap_or(0) + ethereum_addresses.unwrap_or(0)) as u32
+		}
+
+		/// Record that the contribution of `relay_account` is paid to `native_account`.
+		fn record_association(
+			relay_account: &T::RelayChainAccountId,
+			native_account: &T::AccountId,
+		) {
+			ClaimedRelayChainIds::<T>::insert(relay_account, Some(native_account));
+			AssociatedRelayAccounts::<T>::append(native_account, relay_account);
+		}
+
+		/// The first and last block of the vesting period. Vesting starts at genesis unless
+		/// another start block is configured, and lasts `VestingPeriod` blocks unless an end block
+		/// is configured.
+		pub fn vesting_schedule() -> (T::BlockNumber, T::BlockNumber) {
+			let start = VestingStartBlock::<T>::get();
+			let end = VestingEndBlock::<T>::get()
+				.unwrap_or_else(|| start.saturating_add(T::VestingPeriod::get()));
+			(start, end)
+		}
+
+		/// How much of a reward is vested at block `now`: the initial payment plus whatever the
+		/// vesting curve has unlocked of the remainder. Vesting is measured from the start of the
+		/// vesting schedule, regardless of when the reward was associated or last paid.
+		pub fn vested_reward(info: &RewardInfo<T>, now: T::BlockNumber) -> BalanceOf<T> {
+			let (start, end) = Self::vesting_schedule();
+			let vesting_reward = info.total_reward.saturating_sub(info.initial_payment);
+			info.initial_payment.saturating_add(T::VestingCurve::vested(
+				vesting_reward,
+				now.saturating_sub(start),
+				end.saturating_sub(start),
+			))
+		}
+
+		/// Pay `payee` whatever portion of its reward is vested by now and was not claimed yet.
+		/// In `PayoutMode::Lock`, pay the rest of the reward as well and lock it until it vests.
+		/// Returns the amount paid. Nothing is paid, locked or recorded unless all of it succeeds.
+		#[transactional]
+		fn pay(payee: &T::AccountId) -> Result<BalanceOf<T>, DispatchError> {
+			ensure!(
+				Initialized::<T>::get(),
+				Error::<T>::RewardVecNotFullyInitializedYet
+			);
 
 			// Calculate the veted amount on demand.
-			let mut info =
-				AccountsPayable::<T>::get(&payee).ok_or(Error::<T>::NoAssociatedClaim)?;
+			let mut info = AccountsPayable::<T>::get(payee).ok_or(Error::<T>::NoAssociatedClaim)?;
 			ensure!(
 				info.claimed_reward < info.total_reward,
 				Error::<T>::RewardsAlreadyClaimed
 			);
 			let now = frame_system::Pallet::<T>::block_number();
 
-			let payable_per_block = info.total_reward
-				/ T::VestingPeriod::get()
-					.saturated_into::<u128>()
-					.try_into()
-					.ok()
-					.ok_or(Error::<T>::WrongConversionU128ToBalance)?; //TODO safe math;
-			let payable_period = now.saturating_sub(info.last_paid);
-
-			let pay_period_as_balance: BalanceOf<T> = payable_period
-				.saturated_into::<u128>()
-				.try_into()
-				.ok()
-				.ok_or(Error::<T>::WrongConversionU128ToBalance)?;
-
-			// If the period is bigger than whats missing to pay, then return whats missing to pay
-			let payable_amount = if pay_period_as_balance.saturating_mul(payable_per_block)
-				< info.total_reward.saturating_sub(info.claimed_reward)
-			{
-				pay_period_as_balance.saturating_mul(payable_per_block)
-			} else {
-				info.total_reward.saturating_sub(info.claimed_reward)
+			// Pay whatever is vested by now that was not claimed yet, or everything in
+			// `PayoutMode::Lock`. The first claim also releases the initial payment.
+			let payable_amount = Self::claimable(&info, now);
+			let locked_amount = match T::PayoutMode::get() {
+				PayoutMode::Claim => Zero::zero(),
+				PayoutMode::Lock => Self::unvested_claimable(&info, now),
 			};
+			// Checked now so that nothing is paid if the lock cannot be created. The schedule of
+			// an earlier payout is extended instead, but one created by someone else is never
+			// touched.
+			ensure!(
+				locked_amount.is_zero()
+					|| T::VestingSchedule::vesting_balance(payee).is_none()
+					|| Self::reward_lock(payee, now).is_some(),
+				Error::<T>::ExistingVestingSchedule
+			);
+
+			// Make the payment out of the reward pot. Should the lock fail after the transfer, the
+			// transfer is rolled back with it.
+			let pot = Self::account_id();
+			ensure!(
+				T::RewardCurrency::free_balance(&pot) >= payable_amount,
+				Error::<T>::InsufficientRewardPot
+			);
+			T::RewardCurrency::transfer(&pot, payee, payable_amount, AllowDeath)?;
+			if !locked_amount.is_zero() {
+				Self::lock_unvested(payee, locked_amount, now)?;
+			}
 
 			// Update the stored info
 			info.last_paid = now;
 			info.claimed_reward = info.claimed_reward.saturating_add(payable_amount);
-			AccountsPayable::<T>::insert(&payee, &info);
+			AccountsPayable::<T>::insert(payee, &info);
+
+			// Emit event
+			Self::deposit_event(Event::RewardsPaid(payee.clone(), payable_amount));
+
+			Ok(payable_amount)
+		}
+
+		/// Move the reward of `old_reward_account` to `new_reward_account`, merging it with the
+		/// reward already there, if any. Drops any change `old_reward_account` had proposed.
+		/// Returns how many relay accounts and Ethereum addresses were moved along.
+		fn do_update_reward_address(
+			old_reward_account: T::AccountId,
+			new_reward_account: T::AccountId,
+		) -> Result<u32, DispatchError> {
+			let mut info = AccountsPayable::<T>::get(&old_reward_account)
+				.ok_or(Error::<T>::NoAssociatedClaim)?;
+			// Merging a reward with itself would double it
+			ensure!(
+				old_reward_account != new_reward_account,
+				Error::<T>::RewardAddressUnchanged
+			);
+			let moved_associations = Self::associations_of(&old_reward_account);
+			ensure!(
+				moved_associations.saturating_add(Self::associations_of(&new_reward_account))
+					<= T::MaxRelayAccountsPerAccount::get(),
+				Error::<T>::TooManyRelayAccounts
+			);
+
+			if let Some(info_existing_account) = AccountsPayable::<T>::get(&new_reward_account) {
+				info.merge(info_existing_account);
+			}
+
+			// Remove previous rewarded account
+			AccountsPayable::<T>::remove(&old_reward_account);
+			PendingRewardAddresses::<T>::remove(&old_reward_account);
+
+			// Update new rewarded acount
+			AccountsPayable::<T>::insert(&new_reward_account, &info);
 
-			// Make the payment
-			// TODO where are these reward funds coming from? Currently I'm just minting them right here.
-			// 1. We could have an associated type to absorb the imbalance.
-			// 2. We could have this pallet control a pot of funds, and initialize it at genesis.
-			T::RewardCurrency::deposit_creating(&payee, payable_amount);
+			// Vesting schedules stay on the accounts holding the locked funds, and so does
+			// `RewardVestingSchedules`. The new account only extends a schedule it got itself.
+
+			// The contributions are now paid to the new account
+			let relay_accounts = AssociatedRelayAccounts::<T>::take(&old_reward_account);
+			for relay_account in relay_accounts.iter() {
+				ClaimedRelayChainIds::<T>::insert(relay_account, Some(&new_reward_account));
+			}
+			AssociatedRelayAccounts::<T>::mutate(&new_reward_account, |accounts| {
+				accounts.extend(relay_accounts)
+			});
+			let ethereum_addresses = AssociatedEthereumAddresses::<T>::take(&old_reward_account);
+			for ethereum_address in ethereum_addresses.iter() {
+				ClaimedEthereumAddresses::<T>::insert(ethereum_address, Some(&new_reward_account));
+			}
+			AssociatedEthereumAddresses::<T>::mutate(&new_reward_account, |addresses| {
+				addresses.extend(ethereum_addresses)
+			});
 
 			// Emit event
-			Self::deposit_event(Event::RewardsPaid(payee, payable_amount));
+			Self::deposit_event(Event::RewardAddressUpdated(
+				old_reward_account,
+				new_reward_account,
+			));
+
+			Ok(moved_associations)
+		}
+
+		/// The extra weight of a payout that locks the unvested part of the reward. Only charged
+		/// in `PayoutMode::Lock`, the only mode that creates or extends vesting schedules.
+		fn lock_weight() -> Weight {
+			match T::PayoutMode::get() {
+				PayoutMode::Claim => 0,
+				PayoutMode::Lock => T::WeightInfo::lock_payout(),
+			}
+		}
+
+		/// The weight of paying `beneficiaries` accounts in a batch.
+		fn payout_batch_weight(beneficiaries: u32) -> Weight {
+			T::WeightInfo::payout_batch(beneficiaries)
+				.saturating_add(Self::lock_weight().saturating_mul(beneficiaries as Weight))
+		}
+
+		/// Lock `locked_amount` of the balance of `payee` and release it linearly from now (or the
+		/// start of the vesting schedule, if later) until the end of the vesting schedule. What is
+		/// left of the lock of an earlier payout is released the same way, so the two are merged
+		/// into a single schedule.
+		pub(crate) fn lock_unvested(
+			payee: &T::AccountId,
+			mut locked_amount: BalanceOf<T>,
+			now: T::BlockNumber,
+		) -> DispatchResult {
+			if let Some(already_locked) = Self::reward_lock(payee, now) {
+				locked_amount = locked_amount.saturating_add(already_locked);
+				T::VestingSchedule::remove_vesting_schedule(payee);
+			}
+
+			let (start, end) = Self::vesting_schedule();
+			let starting_block = now.max(start);
+			let blocks = end
+				.saturating_sub(starting_block)
+				.max(One::one())
+				.saturated_into::<u128>()
+				.saturated_into::<BalanceOf<T>>();
+			// Round up, so that everything is unlocked by the end of the vesting schedule
+			let mut per_block = locked_amount / blocks;
+			if !(locked_amount % blocks).is_zero() {
+				per_block = per_block.saturating_add(One::one());
+			}
+			T::VestingSchedule::add_vesting_schedule(
+				payee,
+				locked_amount,
+				per_block,
+				starting_block,
+			)?;
+			RewardVestingSchedules::<T>::insert(payee, (locked_amount, per_block, starting_block));
+			Ok(())
+		}
+
+		/// What is still locked by the vesting schedule of an earlier payout to `payee`, if the
+		/// account still has that schedule rather than one created by someone else.
+		fn reward_lock(payee: &T::AccountId, now: T::BlockNumber) -> Option<BalanceOf<T>> {
+			let (locked, per_block, starting_block) = RewardVestingSchedules::<T>::get(payee)?;
+			let elapsed = now
+				.saturating_sub(starting_block)
+				.saturated_into::<u128>()
+				.saturated_into::<BalanceOf<T>>();
+			let still_locked = locked.saturating_sub(per_block.saturating_mul(elapsed));
+			if T::VestingSchedule::vesting_balance(payee)? == still_locked {
+				Some(still_locked)
+			} else {
+				None
+			}
+		}
+
+		/// Visit up to `max_payouts` accounts of `AccountsPayable`, starting after the one visited
+		/// last, and pay those with something to claim. Stops early rather than exceed `budget`.
+		/// Returns the weight consumed.
+		///
+		/// The position is persisted as the raw storage key of the last visited account, so the
+		/// walk resumes there next block even if accounts were added or removed in between. Once
+		/// the end of the map is reached, the next block starts over.
+		fn auto_payout(max_payouts: u32, budget: Weight) -> Weight {
+			let mut consumed = T::DbWeight::get().reads_writes(2, 1);
+			if !Initialized::<T>::get() {
+				return consumed;
+			}
+
+			let prefix = AccountsPayable::<T>::final_prefix();
+			let mut cursor = AutoPayoutCursor::<T>::get().unwrap_or_else(|| prefix.to_vec());
+			let now = frame_system::Pallet::<T>::block_number();
+			// Assume every visit ends up in a payout
+			let visit_weight = T::WeightInfo::claim_for().saturating_add(Self::lock_weight());
+
+			let mut visited = 0u32;
+			let mut reached_end = false;
+			while visited < max_payouts && consumed.saturating_add(visit_weight) <= budget {
+				let key = match sp_io::storage::next_key(&cursor) {
+					Some(key) if key.starts_with(&prefix) => key,
+					_ => {
+						reached_end = true;
+						break;
+					}
+				};
+				visited += 1;
+				consumed = consumed.saturating_add(visit_weight);
+
+				let account = Self::payable_account_at(&prefix, &key);
+				cursor = key;
+				let account = match account {
+					Some(account) => account,
+					None => continue,
+				};
+				let claimable = AccountsPayable::<T>::get(&account)
+					.map(|info| Self::claimable(&info, now))
+					.unwrap_or_else(Zero::zero);
+				if claimable.is_zero() {
+					continue;
+				}
+				if let Err(error) = Self::pay(&account) {
+					warn!(
+						"Rewards: automatic payout to {:?} failed: {:?}",
+						account, error
+					);
+				}
+			}
+
+			if reached_end {
+				AutoPayoutCursor::<T>::kill();
+			} else {
+				AutoPayoutCursor::<T>::put(cursor);
+			}
+			consumed
+		}
+
+		/// The account of the `AccountsPayable` entry stored under the raw `key`. Keys are the
+		/// `prefix`, the 16 bytes of the Blake2_128 hash and the account.
+		fn payable_account_at(prefix: &[u8], key: &[u8]) -> Option<T::AccountId> {
+			key.get(prefix.len() + 16..)
+				.and_then(|mut raw| T::AccountId::decode(&mut raw).ok())
+		}
+
+		/// Pay each of `beneficiaries`, skipping those that cannot be paid with a `PayoutSkipped`
+		/// event.
+		fn do_payout_batch(beneficiaries: Vec<T::AccountId>) -> DispatchResult {
+			ensure!(
+				beneficiaries.len() as u32 <= T::MaxBatchPayouts::get(),
+				Error::<T>::TooManyBeneficiaries
+			);
+			ensure!(
+				Initialized::<T>::get(),
+				Error::<T>::RewardVecNotFullyInitializedYet
+			);
+
+			for beneficiary in beneficiaries {
+				// `pay` checks everything before writing, so a failure leaves no trace
+				if let Err(error) = Self::pay(&beneficiary) {
+					Self::deposit_event(Event::PayoutSkipped(beneficiary, error));
+				}
+			}
+
+			Ok(())
+		}
+
+		/// Look for up to `MaxBatchPayouts` accounts that could claim at least
+		/// `AutoClaimThreshold`, and submit an `auto_claim` for them signed by a local
+		/// `AuthorityId` key.
+		///
+		/// The search starts after the last account of the previous `auto_claim` and wraps around
+		/// at the end of `AccountsPayable`, so accounts that stay claimable because they cannot be
+		/// paid do not keep the others waiting.
+		///
+		/// Runs in the offchain worker, so the whole of `AccountsPayable` is read from the node's
+		/// state at no cost to the chain. Nodes without an authority key simply submit nothing.
+		fn submit_auto_claim(now: T::BlockNumber) -> Result<(), &'static str> {
+			if AutoClaimAuthority::<T>::get().is_none() || !Initialized::<T>::get() {
+				return Ok(());
+			}
+
+			let threshold = T::AutoClaimThreshold::get();
+			let prefix = AccountsPayable::<T>::final_prefix();
+			let cursor = AutoClaimCursor::<T>::get().unwrap_or_else(|| prefix.to_vec());
+			let mut key = cursor.clone();
+			let mut wrapped = false;
+			let mut beneficiaries = Vec::new();
+			while (beneficiaries.len() as u32) < T::MaxBatchPayouts::get() {
+				match sp_io::storage::next_key(&key).filter(|next| next.starts_with(&prefix)) {
+					// Every account was visited once
+					Some(next) if wrapped && next > cursor => break,
+					Some(next) => key = next,
+					None if !wrapped => {
+						wrapped = true;
+						key = prefix.to_vec();
+						continue;
+					}
+					None => break,
+				}
+				let account = match Self::payable_account_at(&prefix, &key) {
+					Some(account) => account,
+					None => continue,
+				};
+				let claimable = AccountsPayable::<T>::get(&account)
+					.map(|info| Self::claimable(&info, now))
+					.unwrap_or_else(Zero::zero);
+				if !claimable.is_zero() && claimable >= threshold {
+					beneficiaries.push(account);
+				}
+			}
+			if beneficiaries.is_empty() {
+				return Ok(());
+			}
+
+			let result = Signer::<T, T::AuthorityId>::any_account().send_unsigned_transaction(
+				|account| AutoClaimPayload {
+					beneficiaries: beneficiaries.clone(),
+					block_number: now,
+					public: account.public.clone(),
+				},
+				|payload, signature| Call::auto_claim(payload, signature),
+			);
+			match result {
+				Some((_, Ok(()))) => Ok(()),
+				Some((_, Err(()))) => Err("failed to submit the transaction"),
+				None => Err("no local authority key"),
+			}
+		}
+
+		/// Whether `payload` is signed by the auto claim authority.
+		fn check_auto_claim(
+			payload: &AutoClaimPayload<T::Public, T::AccountId, T::BlockNumber>,
+			signature: &T::Signature,
+		) -> Result<(), InvalidTransaction> {
+			let authority = AutoClaimAuthority::<T>::get().ok_or(InvalidTransaction::Call)?;
+			ensure!(
+				payload.public.clone().into_account() == authority,
+				InvalidTransaction::BadSigner
+			);
+			ensure!(
+				SignedPayload::<T>::verify::<T::AuthorityId>(payload, signature.clone()),
+				InvalidTransaction::BadProof
+			);
+			ensure!(
+				payload.block_number <= frame_system::Pallet::<T>::block_number(),
+				InvalidTransaction::Future
+			);
+			ensure!(
+				Self::is_fresh_auto_claim(payload.block_number),
+				InvalidTransaction::Stale
+			);
+			ensure!(
+				payload.beneficiaries.len() as u32 <= T::MaxBatchPayouts::get(),
+				InvalidTransaction::ExhaustsResources
+			);
+			Ok(())
+		}
+
+		/// Whether an `auto_claim` made at `block_number` may still be used: it is at most
+		/// `AUTO_CLAIM_LONGEVITY` blocks old, and newer than the last one used. Authority signed
+		/// payloads can therefore not be replayed for free.
+		fn is_fresh_auto_claim(block_number: T::BlockNumber) -> bool {
+			let now = frame_system::Pallet::<T>::block_number();
+			now <= block_number.saturating_add(AUTO_CLAIM_LONGEVITY.into())
+				&& LastAutoClaimBlock::<T>::get().map_or(true, |last| block_number > last)
+		}
+
+		/// How much of a reward can be claimed at block `now`: whatever is vested by then that was
+		/// not claimed yet or, in `PayoutMode::Lock`, whatever was not paid yet. This is what
+		/// `show_me_the_money` pays, so offchain callers should use it (through the runtime API)
+		/// rather than replicate the vesting math.
+		pub fn claimable(info: &RewardInfo<T>, now: T::BlockNumber) -> BalanceOf<T> {
+			match T::PayoutMode::get() {
+				PayoutMode::Claim => {
+					Self::vested_reward(info, now).saturating_sub(info.claimed_reward)
+				}
+				PayoutMode::Lock => info.total_reward.saturating_sub(info.claimed_reward),
+			}
+		}
+
+		/// In `PayoutMode::Lock`, the part of what is claimable at block `now` that is not vested
+		/// yet, and is locked when paid.
+		///
+		/// Earlier payouts already paid and locked their part of the reward, so only the
+		/// contributions associated since then are left. Every contribution vests at the same
+		/// pace, so they are vested pro rata.
+		fn unvested_claimable(info: &RewardInfo<T>, now: T::BlockNumber) -> BalanceOf<T> {
+			let remaining = info.total_reward.saturating_sub(info.claimed_reward);
+			let vested = multiply_by_rational(
+				Self::vested_reward(info, now).saturated_into::<u128>(),
+				remaining.saturated_into::<u128>(),
+				info.total_reward.max(One::one()).saturated_into::<u128>(),
+			)
+			.map(|vested| vested.saturated_into::<BalanceOf<T>>())
+			.unwrap_or(remaining);
+			remaining.saturating_sub(vested)
+		}
+
+		/// How much `account` could claim with `show_me_the_money` in the current block. Zero if
+		/// it has no rewards or payouts have not started yet.
+		pub fn claimable_for(account: &T::AccountId) -> BalanceOf<T> {
+			match AccountsPayable::<T>::get(account) {
+				Some(info) if Initialized::<T>::get() => {
+					Self::claimable(&info, frame_system::Pallet::<T>::block_number())
+				}
+				_ => Zero::zero(),
+			}
+		}
+
+		/// How much of the rewards of `account` are vested in the current block, claimed or not.
+		pub fn vested_for(account: &T::AccountId) -> BalanceOf<T> {
+			AccountsPayable::<T>::get(account)
+				.map(|info| Self::vested_reward(&info, frame_system::Pallet::<T>::block_number()))
+				.unwrap_or_else(Zero::zero)
+		}
+	}
+
+	#[pallet::call]
+	impl<T: Config> Pallet<T> {
+		/// Associate a native rewards_destination identity with a crowdloan contribution.
+		///
+		/// This is a signed call, so whoever submits it pays the fees. Contributors that do not
+		/// have any funds on this chain yet should use `associate_native_identity_unsigned`.
+		/// This is inspired by Polkadot's claims pallet:
+		/// https://github.com/paritytech/polkadot/blob/master/runtime/common/src/claims.rs
+		///
+		/// This function and the entire concept of unassociated contributions may be obviated if
+		/// They will accept a memo filed in the Polkadot crowdloan pallet.
+		#[pallet::weight(T::WeightInfo::associate_native_identity())]
+		pub fn associate_native_identity(
+			origin: OriginFor<T>,
+			reward_account: T::AccountId,
+			relay_account: T::RelayChainAccountId,
+			proof: MultiSignature,
+		) -> DispatchResultWithPostInfo {
+			ensure_signed(origin)?;
+
+			let reward_info = Self::check_association(&reward_account, &relay_account, &proof)?;
+			Self::do_associate(reward_account, relay_account, reward_info);
+
+			Ok(Default::default())
+		}
+
+		/// Associate a native rewards_destination identity with a crowdloan contribution without
+		/// paying any fees.
+		///
+		/// This is an unsigned call because the caller may not have any funds to pay fees with.
+		/// The relay chain signature and the unassociated contribution are checked in
+		/// `validate_unsigned` before the transaction even enters the pool.
+		#[pallet::weight(T::WeightInfo::associate_native_identity_unsigned())]
+		pub fn associate_native_identity_unsigned(
+			origin: OriginFor<T>,
+			reward_account: T::AccountId,
+			relay_account: T::RelayChainAccountId,
+			proof: MultiSignature,
+		) -> DispatchResultWithPostInfo {
+			ensure_none(origin)?;
+
+			let reward_info = Self::check_association(&reward_account, &relay_account, &proof)?;
+			Self::do_associate(reward_account, relay_account, reward_info);
 
 			Ok(Default::default())
 		}
+
+		/// Associate a native rewards_destination identity with a contribution proven against the
+		/// contributions Merkle root.
+		///
+		/// Besides the usual relay chain signature, the caller provides the reward owed to the
+		/// relay account and the Merkle proof of that `(relay_account, reward)` leaf. The
+		/// contribution is only written to storage on this first claim. Like
+		/// `associate_native_identity_unsigned` this is an unsigned call and is checked in
+		/// `validate_unsigned`.
+		#[pallet::weight(T::WeightInfo::associate_native_identity_with_proof(merkle_proof.len() as u32))]
+		pub fn associate_native_identity_with_proof(
+			origin: OriginFor<T>,
+			reward_account: T::AccountId,
+			relay_account: T::RelayChainAccountId,
+			reward: BalanceOf<T>,
+			proof: MultiSignature,
+			merkle_proof: Vec<H256>,
+		) -> DispatchResultWithPostInfo {
+			ensure_none(origin)?;
+
+			let reward_info = Self::check_merkle_association(
+				&reward_account,
+				&relay_account,
+				reward,
+				&proof,
+				&merkle_proof,
+			)?;
+			Self::do_associate(reward_account, relay_account, reward_info);
+
+			Ok(Default::default())
+		}
+
+		/// Set the root of the Merkle tree of `(relay_account, reward)` leaves against which
+		/// `associate_native_identity_with_proof` checks contributions.
+		#[pallet::weight(T::WeightInfo::set_contributions_merkle_root())]
+		pub fn set_contributions_merkle_root(
+			origin: OriginFor<T>,
+			root: H256,
+		) -> DispatchResultWithPostInfo {
+			ensure_root(origin)?;
+
+			ContributionsMerkleRoot::<T>::put(root);
+			Self::deposit_event(Event::ContributionsMerkleRootSet(root));
+
+			Ok(Default::default())
+		}
+
+		/// Associate a native rewards_destination identity with a contribution made offchain from
+		/// an Ethereum wallet.
+		///
+		/// The proof is an `eth_sign` (EIP-191) signature of `association_message` by the
+		/// contributing address, so no Substrate keys are involved. Like
+		/// `associate_native_identity_unsigned` this is an unsigned call and is checked in
+		/// `validate_unsigned`.
+		#[pallet::weight(T::WeightInfo::associate_ethereum_identity())]
+		pub fn associate_ethereum_identity(
+			origin: OriginFor<T>,
+			reward_account: T::AccountId,
+			ethereum_address: H160,
+			proof: ecdsa::Signature,
+		) -> DispatchResultWithPostInfo {
+			ensure_none(origin)?;
+
+			let reward_info =
+				Self::check_ethereum_association(&reward_account, &ethereum_address, &proof)?;
+
+			Self::add_reward(&reward_account, reward_info.clone());
+			UnassociatedEthereumContributions::<T>::remove(&ethereum_address);
+			ClaimedEthereumAddresses::<T>::insert(&ethereum_address, Some(&reward_account));
+			AssociatedEthereumAddresses::<T>::append(&reward_account, ethereum_address);
+
+			Self::deposit_event(Event::EthereumIdentityAssociated(
+				ethereum_address,
+				reward_account,
+				reward_info.total_reward,
+			));
+
+			Ok(Default::default())
+		}
+
 		/// Collect whatever portion of your reward are currently vested.
-		#[pallet::weight(0)]
+		#[pallet::weight(
+			T::WeightInfo::show_me_the_money().saturating_add(Pallet::<T>::lock_weight())
+		)]
+		pub fn show_me_the_money(origin: OriginFor<T>) -> DispatchResultWithPostInfo {
+			let payee = ensure_signed(origin)?;
+			Self::pay(&payee)?;
+
+			Ok(Default::default())
+		}
+
+		/// Pay `beneficiary` whatever portion of its reward is currently vested.
+		///
+		/// Anyone can trigger a payout on behalf of a contributor, eg a relayer or a scheduled
+		/// bot, and pays the fees for it. The funds always go to the beneficiary's registered
+		/// reward account, exactly as if it had called `show_me_the_money` itself.
+		#[pallet::weight(T::WeightInfo::claim_for().saturating_add(Pallet::<T>::lock_weight()))]
+		pub fn claim_for(
+			origin: OriginFor<T>,
+			beneficiary: T::AccountId,
+		) -> DispatchResultWithPostInfo {
+			ensure_signed(origin)?;
+			Self::pay(&beneficiary)?;
+
+			Ok(Default::default())
+		}
+
+		/// Pay each of `beneficiaries` whatever portion of its reward is currently vested.
+		///
+		/// Like `claim_for`, for up to `MaxBatchPayouts` beneficiaries at once. A beneficiary that
+		/// cannot be paid (eg nothing left to claim) is skipped with a `PayoutSkipped` event and
+		/// does not fail the batch.
+		#[pallet::weight(Pallet::<T>::payout_batch_weight(beneficiaries.len() as u32))]
+		pub fn payout_batch(
+			origin: OriginFor<T>,
+			beneficiaries: Vec<T::AccountId>,
+		) -> DispatchResultWithPostInfo {
+			ensure_signed(origin)?;
+			Self::do_payout_batch(beneficiaries)?;
+
+			Ok(Default::default())
+		}
+
+		/// Pay the beneficiaries of `payload` whatever portion of their rewards is currently
+		/// vested, like `payout_batch`.
+		///
+		/// This is the unsigned transaction submitted by the offchain worker. `validate_unsigned`
+		/// checks that the payload is signed by `AutoClaimAuthority`, so nobody pays fees for it.
+		#[pallet::weight(
+			Pallet::<T>::payout_batch_weight(payload.beneficiaries.len() as u32)
+				.saturating_add(T::DbWeight::get().reads_writes(1, 2))
+		)]
+		pub fn auto_claim(
+			origin: OriginFor<T>,
+			payload: AutoClaimPayload<T::Public, T::AccountId, T::BlockNumber>,
+			_signature: T::Signature,
+		) -> DispatchResultWithPostInfo {
+			ensure_none(origin)?;
+			// Also checked in `validate_unsigned`, but several may have entered the same block
+			ensure!(
+				Self::is_fresh_auto_claim(payload.block_number),
+				Error::<T>::StaleAutoClaim
+			);
+			if let Some(last) = payload.beneficiaries.last() {
+				AutoClaimCursor::<T>::put(AccountsPayable::<T>::hashed_key_for(last));
+			}
+			Self::do_payout_batch(payload.beneficiaries)?;
+			LastAutoClaimBlock::<T>::put(payload.block_number);
+
+			Ok(Default::default())
+		}
+
+		/// Set the account whose key the offchain worker signs `auto_claim` payloads with, or
+		/// disable automatic claims with `None`.
+		#[pallet::weight(T::WeightInfo::set_auto_claim_authority())]
+		pub fn set_auto_claim_authority(
+			origin: OriginFor<T>,
+			authority: Option<T::AccountId>,
+		) -> DispatchResultWithPostInfo {
+			ensure_root(origin)?;
+
+			AutoClaimAuthority::<T>::set(authority.clone());
+			Self::deposit_event(Event::AutoClaimAuthoritySet(authority));
+
+			Ok(Default::default())
+		}
+
+		/// Move your reward to `new_reward_account`.
+		///
+		/// If the new account already has a reward, the two are merged with `RewardInfo::merge`,
+		/// keeping what each has claimed and vested so far.
+		#[pallet::weight(T::WeightInfo::update_reward_address(
+			T::MaxRelayAccountsPerAccount::get()
+		))]
 		pub fn update_reward_address(
 			origin: OriginFor<T>,
 			new_reward_account: T::AccountId,
 		) -> DispatchResultWithPostInfo {
 			let signer = ensure_signed(origin)?;
+			let moved = Self::do_update_reward_address(signer, new_reward_account)?;
 
-			// Calculate the veted amount on demand.
-			let mut info =
-				AccountsPayable::<T>::get(&signer).ok_or(Error::<T>::NoAssociatedClaim)?;
+			Ok(Some(T::WeightInfo::update_reward_address(moved)).into())
+		}
 
-			if let Some(info_existing_account) = AccountsPayable::<T>::get(&new_reward_account) {
-				info.total_reward = info
-					.total_reward
-					.saturating_add(info_existing_account.total_reward);
-				info.claimed_reward = info
-					.claimed_reward
-					.saturating_add(info_existing_account.claimed_reward);
+		/// Propose to move your reward to `new_reward_account`, which must accept it with
+		/// `accept_reward_address` within `RewardAddressProposalExpiry` blocks.
+		///
+		/// Unlike `update_reward_address`, a mistyped account cannot accept, so the reward is never
+		/// moved out of reach. A new proposal replaces the pending one.
+		#[pallet::weight(T::WeightInfo::propose_reward_address())]
+		pub fn propose_reward_address(
+			origin: OriginFor<T>,
+			new_reward_account: T::AccountId,
+		) -> DispatchResultWithPostInfo {
+			let signer = ensure_signed(origin)?;
+			ensure!(
+				AccountsPayable::<T>::contains_key(&signer),
+				Error::<T>::NoAssociatedClaim
+			);
+
+			let expires_at = frame_system::Pallet::<T>::block_number()
+				.saturating_add(T::RewardAddressProposalExpiry::get());
+			PendingRewardAddresses::<T>::insert(
+				&signer,
+				PendingRewardAddress {
+					new_reward_account: new_reward_account.clone(),
+					expires_at,
+				},
+			);
+
+			Self::deposit_event(Event::RewardAddressProposed(
+				signer,
+				new_reward_account,
+				expires_at,
+			));
+
+			Ok(Default::default())
+		}
+
+		/// Accept the reward of `current_reward_account`, which proposed to move it to you with
+		/// `propose_reward_address`. If you already have a reward, the two are merged.
+		#[pallet::weight(T::WeightInfo::accept_reward_address(
+			T::MaxRelayAccountsPerAccount::get()
+		))]
+		pub fn accept_reward_address(
+			origin: OriginFor<T>,
+			current_reward_account: T::AccountId,
+		) -> DispatchResultWithPostInfo {
+			let signer = ensure_signed(origin)?;
+			let pending = PendingRewardAddresses::<T>::get(&current_reward_account)
+				.filter(|pending| pending.new_reward_account == signer)
+				.ok_or(Error::<T>::NoPendingRewardAddress)?;
+			ensure!(
+				frame_system::Pallet::<T>::block_number() <= pending.expires_at,
+				Error::<T>::RewardAddressProposalExpired
+			);
+
+			let moved = Self::do_update_reward_address(current_reward_account, signer)?;
+
+			Ok(Some(T::WeightInfo::accept_reward_address(moved)).into())
+		}
+
+		/// Withdraw your pending `propose_reward_address`, eg because it was mistyped.
+		#[pallet::weight(T::WeightInfo::cancel_reward_address())]
+		pub fn cancel_reward_address(origin: OriginFor<T>) -> DispatchResultWithPostInfo {
+			let signer = ensure_signed(origin)?;
+			ensure!(
+				PendingRewardAddresses::<T>::contains_key(&signer),
+				Error::<T>::NoPendingRewardAddress
+			);
+
+			PendingRewardAddresses::<T>::remove(&signer);
+			Self::deposit_event(Event::RewardAddressProposalCancelled(signer));
+
+			Ok(Default::default())
+		}
+
+		/// Register a chunk of contributions after genesis.
+		///
+		/// This lets a parachain that wins a slot after launch use this pallet. Each entry is the
+		/// relay account, optionally the native account it is already associated with, and the
+		/// _reward_ owed (ie the reward ratio is applied offchain). Chunks are bounded by
+		/// `MaxInitContributors` to keep each block within its weight limit. Once every chunk is
+		/// in, `complete_initialization` locks the set.
+		#[pallet::weight(T::WeightInfo::initialize_reward_vec(rewards.len() as u32))]
+		pub fn initialize_reward_vec(
+			origin: OriginFor<T>,
+			rewards: Vec<(T::RelayChainAccountId, Option<T::AccountId>, BalanceOf<T>)>,
+		) -> DispatchResultWithPostInfo {
+			T::InitializationOrigin::ensure_origin(origin)?;
+			ensure!(
+				!Initialized::<T>::get(),
+				Error::<T>::RewardVecAlreadyInitialized
+			);
+			ensure!(
+				rewards.len() as u32 <= T::MaxInitContributors::get(),
+				Error::<T>::TooManyContributors
+			);
+
+			// Validate the whole chunk before writing anything
+			let mut relay_accounts = BTreeSet::new();
+			let mut relay_accounts_per_account = BTreeMap::new();
+			for (relay_account, native_account, _) in rewards.iter() {
+				ensure!(
+					relay_accounts.insert(relay_account)
+						&& !ClaimedRelayChainIds::<T>::contains_key(relay_account)
+						&& UnassociatedContributions::<T>::get(relay_account).is_none(),
+					Error::<T>::RelayAccountAlreadyRegistered
+				);
+				if let Some(native_account) = native_account {
+					let count = relay_accounts_per_account
+						.entry(native_account)
+						.or_insert_with(|| Self::associations_of(native_account));
+					*count += 1;
+					ensure!(
+						*count <= T::MaxRelayAccountsPerAccount::get(),
+						Error::<T>::TooManyRelayAccounts
+					);
+				}
 			}
 
-			// Remove previous rewarded account
-			AccountsPayable::<T>::remove(&signer);
+			let mut initialized_amount = InitializedRewardAmount::<T>::get();
+			let contributors = rewards.len() as u32;
+			for (relay_account, native_account, reward) in rewards {
+				let reward_info = RewardInfo::<T>::new(reward);
+				match native_account {
+					Some(native_account) => {
+						Self::add_reward(&native_account, reward_info);
+						Self::record_association(&relay_account, &native_account);
+					}
+					None => UnassociatedContributions::<T>::insert(relay_account, reward_info),
+				}
+				initialized_amount = initialized_amount.saturating_add(reward);
+			}
+			InitializedRewardAmount::<T>::put(initialized_amount);
 
-			// Update new rewarded acount
-			AccountsPayable::<T>::insert(&new_reward_account, &info);
+			Self::deposit_event(Event::RewardsInitialized(contributors, initialized_amount));
 
-			// Emit event
-			Self::deposit_event(Event::RewardAddressUpdated(signer, new_reward_account));
+			Ok(Default::default())
+		}
+
+		/// Set the block at which vesting starts and the block at which rewards are fully vested,
+		/// eg to match the lease of the parachain slot.
+		///
+		/// The schedule can only be changed while the set of contributions is being initialized
+		/// (no rewards are paid then) or before the current schedule starts. It cannot start in
+		/// the past. So rewards that have started vesting never vest any slower.
+		#[pallet::weight(T::WeightInfo::set_vesting_schedule())]
+		pub fn set_vesting_schedule(
+			origin: OriginFor<T>,
+			start: T::BlockNumber,
+			end: T::BlockNumber,
+		) -> DispatchResultWithPostInfo {
+			T::InitializationOrigin::ensure_origin(origin)?;
+			let now = frame_system::Pallet::<T>::block_number();
+			ensure!(
+				!Initialized::<T>::get() || now < VestingStartBlock::<T>::get(),
+				Error::<T>::VestingAlreadyStarted
+			);
+			ensure!(
+				now <= start && start < end,
+				Error::<T>::InvalidVestingSchedule
+			);
+
+			VestingStartBlock::<T>::put(start);
+			VestingEndBlock::<T>::put(end);
+
+			Self::deposit_event(Event::VestingScheduleSet(start, end));
 
 			Ok(Default::default())
 		}
+
+		/// Lock the set of contributions registered through `initialize_reward_vec`.
+		///
+		/// Fails unless the rewards registered so far add up to exactly `expected_total`, which
+		/// guards against a chunk having been left out or submitted twice. Payouts only start
+		/// once initialization is complete.
+		#[pallet::weight(T::WeightInfo::complete_initialization())]
+		pub fn complete_initialization(
+			origin: OriginFor<T>,
+			expected_total: BalanceOf<T>,
+		) -> DispatchResultWithPostInfo {
+			T::InitializationOrigin::ensure_origin(origin)?;
+			ensure!(
+				!Initialized::<T>::get(),
+				Error::<T>::RewardVecAlreadyInitialized
+			);
+			let initialized_amount = InitializedRewardAmount::<T>::get();
+			ensure!(
+				initialized_amount == expected_total,
+				Error::<T>::InitializedRewardAmountMismatch
+			);
+
+			Initialized::<T>::put(true);
+
+			Self::deposit_event(Event::InitializationCompleted(initialized_amount));
+
+			Ok(Default::default())
+		}
+	}
+
+	#[pallet::validate_unsigned]
+	impl<T: Config> ValidateUnsigned for Pallet<T> {
+		type Call = Call<T>;
+
+		fn validate_unsigned(_source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+			let to_invalid = |e| match e {
+				Error::<T>::InvalidClaimSignature => InvalidTransaction::BadProof,
+				Error::<T>::InvalidMerkleProof => InvalidTransaction::BadProof,
+				Error::<T>::AlreadyAssociated => InvalidTransaction::Stale,
+				Error::<T>::TooManyRelayAccounts => InvalidTransaction::ExhaustsResources,
+				_ => InvalidTransaction::Custom(ValidityError::NoUnassociatedContribution as u8),
+			};
+			let builder = ValidTransaction::with_tag_prefix("CrowdloanRewardsAssociation")
+				.priority(T::UnsignedPriority::get())
+				// It becomes stale as soon as the contributor is associated
+				.longevity(TransactionLongevity::max_value())
+				.propagate(true);
+
+			match call {
+				Call::associate_native_identity_unsigned(reward_account, relay_account, proof) => {
+					Self::check_association(reward_account, relay_account, proof)
+						.map_err(to_invalid)?;
+					// Only one association per relay account can be in the pool at a time
+					builder.and_provides(relay_account).build()
+				}
+				Call::associate_native_identity_with_proof(
+					reward_account,
+					relay_account,
+					reward,
+					proof,
+					merkle_proof,
+				) => {
+					Self::check_merkle_association(
+						reward_account,
+						relay_account,
+						*reward,
+						proof,
+						merkle_proof,
+					)
+					.map_err(to_invalid)?;
+					builder.and_provides(relay_account).build()
+				}
+				Call::associate_ethereum_identity(reward_account, ethereum_address, proof) => {
+					Self::check_ethereum_association(reward_account, ethereum_address, proof)
+						.map_err(to_invalid)?;
+					builder.and_provides(ethereum_address).build()
+				}
+				Call::auto_claim(payload, signature) => {
+					Self::check_auto_claim(payload, signature)?;
+					ValidTransaction::with_tag_prefix("CrowdloanRewardsAutoClaim")
+						.priority(T::UnsignedPriority::get())
+						// One automatic claim per block
+						.and_provides(payload.block_number)
+						.longevity(AUTO_CLAIM_LONGEVITY.into())
+						.propagate(true)
+						.build()
+				}
+				_ => InvalidTransaction::Call.into(),
+			}
+		}
+	}
+
+	/// How many blocks an `auto_claim` stays valid in the pool. The offchain worker submits a
+	/// fresh one whenever it finds claimable rewards anyway.
+	const AUTO_CLAIM_LONGEVITY: u32 = 5;
+
+	/// Custom validity errors used while validating unsigned transactions.
+	#[repr(u8)]
+	pub enum ValidityError {
+		/// The relay account has no contribution waiting to be associated.
+		NoUnassociatedContribution = 0,
 	}
 
 	#[pallet::error]
@@ -292,22 +1560,193 @@ pub mod pallet {
 		/// User trying to claim rewards has already claimed all rewards associated with its
 		/// identity and contribution
 		RewardsAlreadyClaimed,
-		/// Invalid conversion while calculating payable amount
-		WrongConversionU128ToBalance,
+		/// The reward pot does not hold enough funds to make the requested payment
+		InsufficientRewardPot,
+		/// The Merkle proof does not show the contribution is part of the contributions root
+		InvalidMerkleProof,
+		/// The set of contributions was already locked by `complete_initialization`
+		RewardVecAlreadyInitialized,
+		/// Rewards cannot be paid until the set of contributions is complete
+		RewardVecNotFullyInitializedYet,
+		/// The chunk of contributions is larger than `MaxInitContributors`
+		TooManyContributors,
+		/// The relay account is already registered, or appears twice in the chunk
+		RelayAccountAlreadyRegistered,
+		/// The rewards registered so far do not add up to the expected total
+		InitializedRewardAmountMismatch,
+		/// The vesting schedule cannot be changed once vesting has started
+		VestingAlreadyStarted,
+		/// The batch of beneficiaries is larger than `MaxBatchPayouts`
+		TooManyBeneficiaries,
+		/// The vesting schedule must start no earlier than the current block and end after it
+		/// starts
+		InvalidVestingSchedule,
+		/// The unvested reward cannot be locked because the account already has a vesting
+		/// schedule. It can be paid once the reward is fully vested.
+		ExistingVestingSchedule,
+		/// The account has not been proposed as the new reward address of that reward
+		NoPendingRewardAddress,
+		/// The proposed reward address change was not accepted in time
+		RewardAddressProposalExpired,
+		/// The new reward address is the current one
+		RewardAddressUnchanged,
+		/// The native account would be paid the contributions of more than
+		/// `MaxRelayAccountsPerAccount` relay accounts and Ethereum addresses
+		TooManyRelayAccounts,
+		/// The automatic claim is too old, or not newer than the last one used
+		StaleAutoClaim,
 	}
 
 	#[pallet::storage]
 	#[pallet::getter(fn accounts_payable)]
 	pub type AccountsPayable<T: Config> =
 		StorageMap<_, Blake2_128Concat, T::AccountId, RewardInfo<T>>;
+	/// The native account each associated relay account's contribution is paid to. Associations
+	/// that predate `Releases::V3_0_0` did not record it and hold `None` instead.
 	#[pallet::storage]
 	#[pallet::getter(fn claimed_relay_chain_ids)]
 	pub type ClaimedRelayChainIds<T: Config> =
-		StorageMap<_, Blake2_128Concat, T::RelayChainAccountId, ()>;
+		StorageMap<_, Blake2_128Concat, T::RelayChainAccountId, Option<T::AccountId>>;
+	/// The relay accounts whose contributions are paid to each native account, ie
+	/// `ClaimedRelayChainIds` the other way around.
+	#[pallet::storage]
+	#[pallet::getter(fn associated_relay_accounts)]
+	pub type AssociatedRelayAccounts<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, Vec<T::RelayChainAccountId>, ValueQuery>;
 	#[pallet::storage]
 	#[pallet::getter(fn unassociated_contributions)]
 	pub type UnassociatedContributions<T: Config> =
 		StorageMap<_, Blake2_128Concat, T::RelayChainAccountId, RewardInfo<T>>;
+	/// Whether the set of contributions is complete. Genesis contributions complete it right
+	/// away, otherwise `complete_initialization` does.
+	#[pallet::storage]
+	#[pallet::getter(fn initialized)]
+	pub type Initialized<T: Config> = StorageValue<_, bool, ValueQuery>;
+	/// The total reward registered so far, at genesis or through `initialize_reward_vec`.
+	#[pallet::storage]
+	#[pallet::getter(fn initialized_reward_amount)]
+	pub type InitializedRewardAmount<T: Config> = StorageValue<_, BalanceOf<T>, ValueQuery>;
+	/// The root of the Merkle tree of `(relay_account, reward)` leaves, for contributions that
+	/// are not enumerated in `UnassociatedContributions`.
+	#[pallet::storage]
+	#[pallet::getter(fn contributions_merkle_root)]
+	pub type ContributionsMerkleRoot<T: Config> = StorageValue<_, H256>;
+	/// The native account each associated Ethereum address's contribution is paid to.
+	/// Associations that predate `Releases::V3_0_0` did not record it and hold `None` instead.
+	#[pallet::storage]
+	#[pallet::getter(fn claimed_ethereum_addresses)]
+	pub type ClaimedEthereumAddresses<T: Config> =
+		StorageMap<_, Blake2_128Concat, H160, Option<T::AccountId>>;
+	/// The Ethereum addresses whose contributions are paid to each native account, ie
+	/// `ClaimedEthereumAddresses` the other way around.
+	#[pallet::storage]
+	#[pallet::getter(fn associated_ethereum_addresses)]
+	pub type AssociatedEthereumAddresses<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, Vec<H160>, ValueQuery>;
+	#[pallet::storage]
+	#[pallet::getter(fn unassociated_ethereum_contributions)]
+	pub type UnassociatedEthereumContributions<T: Config> =
+		StorageMap<_, Blake2_128Concat, H160, RewardInfo<T>>;
+	/// The block at which vesting starts. Zero, ie genesis, unless configured otherwise.
+	#[pallet::storage]
+	#[pallet::getter(fn vesting_start_block)]
+	pub type VestingStartBlock<T: Config> = StorageValue<_, T::BlockNumber, ValueQuery>;
+	/// The block at which rewards are fully vested. `VestingPeriod` blocks after the start unless
+	/// configured otherwise.
+	#[pallet::storage]
+	#[pallet::getter(fn vesting_end_block)]
+	pub type VestingEndBlock<T: Config> = StorageValue<_, T::BlockNumber>;
+	/// The raw storage key of the `AccountsPayable` entry automatic payouts visited last.
+	#[pallet::storage]
+	pub type AutoPayoutCursor<T: Config> = StorageValue<_, Vec<u8>>;
+	/// The raw storage key of the `AccountsPayable` entry of the last beneficiary of an
+	/// `auto_claim`. The offchain worker looks for the next beneficiaries after it.
+	#[pallet::storage]
+	pub type AutoClaimCursor<T: Config> = StorageValue<_, Vec<u8>>;
+	/// The vesting schedule created by the `PayoutMode::Lock` payouts of each account, as
+	/// `(locked, per_block, starting_block)`. Later payouts only extend an account's schedule while
+	/// it still matches, and never touch schedules created by someone else.
+	#[pallet::storage]
+	#[pallet::getter(fn reward_vesting_schedules)]
+	pub type RewardVestingSchedules<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, (BalanceOf<T>, BalanceOf<T>, T::BlockNumber)>;
+	/// Reward address changes proposed by the current reward account, waiting for the new
+	/// account to accept them.
+	#[pallet::storage]
+	#[pallet::getter(fn pending_reward_addresses)]
+	pub type PendingRewardAddresses<T: Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		PendingRewardAddress<T::AccountId, T::BlockNumber>,
+	>;
+	/// The account whose key signs the `auto_claim` payloads of the offchain worker. Automatic
+	/// claims are disabled while this is not set.
+	#[pallet::storage]
+	#[pallet::getter(fn auto_claim_authority)]
+	pub type AutoClaimAuthority<T: Config> = StorageValue<_, T::AccountId>;
+	/// The block of the last `auto_claim` payload used. Only newer ones are accepted.
+	#[pallet::storage]
+	#[pallet::getter(fn last_auto_claim_block)]
+	pub type LastAutoClaimBlock<T: Config> = StorageValue<_, T::BlockNumber>;
+	/// The layout of the pallet storage, used to decide which migrations to run.
+	#[pallet::storage]
+	pub type StorageVersion<T: Config> = StorageValue<_, Releases, ValueQuery>;
+
+	/// An amount of relay chain currency, in planck.
+	///
+	/// Real contributions do not fit in a `u32` (1 KSM is 10^12 planck) and are too large for
+	/// JavaScript numbers, so this is serialized as a decimal string. Plain JSON integers are
+	/// accepted when deserializing too.
+	#[derive(
+		Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Encode, Decode, RuntimeDebug,
+	)]
+	pub struct RelayChainBalance(pub u128);
+
+	impl From<u128> for RelayChainBalance {
+		fn from(amount: u128) -> Self {
+			RelayChainBalance(amount)
+		}
+	}
+
+	#[cfg(feature = "std")]
+	impl serde::Serialize for RelayChainBalance {
+		fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+			serializer.serialize_str(&self.0.to_string())
+		}
+	}
+
+	#[cfg(feature = "std")]
+	impl<'de> serde::Deserialize<'de> for RelayChainBalance {
+		fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+			struct RelayChainBalanceVisitor;
+
+			impl<'de> serde::de::Visitor<'de> for RelayChainBalanceVisitor {
+				type Value = RelayChainBalance;
+
+				fn expecting(&self, formatter: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+					formatter.write_str("a relay chain balance as a decimal string or an integer")
+				}
+
+				fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+					value
+						.parse::<u128>()
+						.map(RelayChainBalance)
+						.map_err(E::custom)
+				}
+
+				fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Self::Value, E> {
+					Ok(RelayChainBalance(value.into()))
+				}
+
+				fn visit_u128<E: serde::de::Error>(self, value: u128) -> Result<Self::Value, E> {
+					Ok(RelayChainBalance(value))
+				}
+			}
+
+			deserializer.deserialize_any(RelayChainBalanceVisitor)
+		}
+	}
 
 	// Design decision:
 	// Genesis config contributions are specified in relay-chain currency
@@ -316,15 +1755,28 @@ pub mod pallet {
 	#[pallet::genesis_config]
 	pub struct GenesisConfig<T: Config> {
 		/// Contributions that have a native account id associated already.
-		pub associated: Vec<(T::RelayChainAccountId, T::AccountId, u32)>,
+		pub associated: Vec<(T::RelayChainAccountId, T::AccountId, RelayChainBalance)>,
 		/// Contributions that will need a native account id to be associated through an extrinsic.
-		pub unassociated: Vec<(T::RelayChainAccountId, u32)>,
+		pub unassociated: Vec<(T::RelayChainAccountId, RelayChainBalance)>,
+		/// Contributions made offchain from Ethereum wallets. They are associated through an
+		/// extrinsic carrying an `eth_sign` signature by the contributing address.
+		pub unassociated_ethereum: Vec<(H160, RelayChainBalance)>,
+		/// The root of a Merkle tree of `(relay_account, reward)` leaves, for crowdloans too large
+		/// to enumerate in genesis. Note that the leaves hold _rewards_, so the reward ratio must
+		/// be applied offchain when building the tree. See the `merkle` module.
+		pub contributions_merkle_root: Option<H256>,
 		/// The ratio of (reward tokens to be paid) / (relay chain funds contributed)
-		/// This is dead stupid simple using a u32. So the reward amount has to be an integer
-		/// multiple of the contribution amount. A better fixed-ratio solution would be
-		/// https://crates.parity.io/sp_arithmetic/fixed_point/struct.FixedU128.html
+		/// This is a fixed point number so the reward does not need to be an integer multiple of
+		/// the contribution (eg 0.37 reward planck per relay planck). In JSON chain specs it is
+		/// written as its inner value, ie the ratio times 10^18, as a string.
+		/// Rewards are always rounded down. See `GenesisConfig::reward_for`.
 		/// We could also do something fancy and non-linear if the need arises.
-		pub reward_ratio: u32,
+		pub reward_ratio: FixedU128,
+		/// The block at which vesting starts, eg the start of the relay chain lease.
+		pub vesting_start_block: T::BlockNumber,
+		/// The block at which rewards are fully vested. Defaults to `VestingPeriod` blocks after
+		/// the start.
+		pub vesting_end_block: Option<T::BlockNumber>,
 	}
 
 	#[cfg(feature = "std")]
@@ -333,45 +1785,142 @@ pub mod pallet {
 			Self {
 				associated: Vec::new(),
 				unassociated: Vec::new(),
-				reward_ratio: 1,
+				unassociated_ethereum: Vec::new(),
+				contributions_merkle_root: None,
+				reward_ratio: FixedU128::saturating_from_integer(1u128),
+				vesting_start_block: Zero::zero(),
+				vesting_end_block: None,
 			}
 		}
 	}
 
+	impl<T: Config> GenesisConfig<T> {
+		/// The reward owed for a contribution of `contribution` relay chain planck.
+		///
+		/// Rounding policy: the product is always rounded down to the nearest reward planck. The
+		/// pallet therefore never owes more than `reward_ratio` times the total contributed, and
+		/// each contributor loses strictly less than one reward planck of dust. Contributions so
+		/// small that they round down to zero are still registered, with a zero reward.
+		pub fn reward_for(&self, contribution: u128) -> BalanceOf<T> {
+			self.reward_ratio
+				.saturating_mul_int(contribution)
+				.saturated_into::<BalanceOf<T>>()
+		}
+	}
+
 	#[pallet::genesis_build]
 	impl<T: Config> GenesisBuild<T> for GenesisConfig<T> {
 		fn build(&self) {
 			// Warn if no contributions (associated or not) are specified
-			if self.associated.is_empty() && self.unassociated.is_empty() {
+			if self.associated.is_empty()
+				&& self.unassociated.is_empty()
+				&& self.unassociated_ethereum.is_empty()
+				&& self.contributions_merkle_root.is_none()
+			{
 				warn!("Rewards: No contributions configured. Pallet will not be useable.")
 			}
 
+			// Validate the contributions before writing anything
+			let mut relay_accounts = BTreeSet::new();
+			let mut ethereum_addresses = BTreeSet::new();
+			let mut total_reward: u128 = 0;
+			let mut check_contribution = |contrib: &RelayChainBalance| {
+				assert!(
+					contrib.0 > 0,
+					"Rewards: Contribution amounts in genesis must be non zero"
+				);
+				total_reward = total_reward
+					.checked_add(self.reward_ratio.saturating_mul_int(contrib.0))
+					.expect("Rewards: Total reward in genesis overflows u128");
+			};
+			let relay_contributions = self
+				.associated
+				.iter()
+				.map(|(relay_account, _, contrib)| (relay_account, contrib))
+				.chain(
+					self.unassociated
+						.iter()
+						.map(|(relay_account, contrib)| (relay_account, contrib)),
+				);
+			for (relay_account, contrib) in relay_contributions {
+				check_contribution(contrib);
+				assert!(
+					relay_accounts.insert(relay_account),
+					"Rewards: Duplicate relay chain account in genesis contributions"
+				);
+			}
+			let mut relay_accounts_per_account = BTreeMap::new();
+			for (_, native_account, _) in self.associated.iter() {
+				let count = relay_accounts_per_account
+					.entry(native_account)
+					.or_insert(0u32);
+				*count += 1;
+				assert!(
+					*count <= T::MaxRelayAccountsPerAccount::get(),
+					"Rewards: Too many relay chain accounts for one native account in genesis"
+				);
+			}
+			for (ethereum_address, contrib) in self.unassociated_ethereum.iter() {
+				check_contribution(contrib);
+				assert!(
+					ethereum_addresses.insert(ethereum_address),
+					"Rewards: Duplicate ethereum address in genesis contributions"
+				);
+			}
+			let total_reward = BalanceOf::<T>::try_from(total_reward)
+				.ok()
+				.expect("Rewards: Total reward in genesis overflows the reward balance");
+
+			StorageVersion::<T>::put(Releases::LATEST);
+
+			VestingStartBlock::<T>::put(self.vesting_start_block);
+			if let Some(end) = self.vesting_end_block {
+				assert!(
+					end > self.vesting_start_block,
+					"Rewards: Vesting must end after it starts"
+				);
+				VestingEndBlock::<T>::put(end);
+			}
+
+			// Contributions configured at genesis are final. Otherwise the set is filled through
+			// `initialize_reward_vec` and locked by `complete_initialization`.
+			InitializedRewardAmount::<T>::put(total_reward);
+			if !self.associated.is_empty()
+				|| !self.unassociated.is_empty()
+				|| !self.unassociated_ethereum.is_empty()
+				|| self.contributions_merkle_root.is_some()
+			{
+				Initialized::<T>::put(true);
+			}
+
 			// Initialize storage for associated contributions
 			self.associated
 				.iter()
 				.for_each(|(relay_account, native_account, contrib)| {
-					let reward_info = RewardInfo {
-						total_reward: BalanceOf::<T>::from(*contrib)
-							.saturating_mul(BalanceOf::<T>::from(self.reward_ratio)),
-						claimed_reward: 0u32.into(),
-						last_paid: 0u32.into(),
-					};
-					AccountsPayable::<T>::insert(native_account, reward_info);
-					ClaimedRelayChainIds::<T>::insert(relay_account, ());
+					let reward_info = RewardInfo::<T>::new(self.reward_for(contrib.0));
+					Pallet::<T>::add_reward(native_account, reward_info);
+					Pallet::<T>::record_association(relay_account, native_account);
 				});
 
 			// Initialize storage for UN-associated contributions
 			self.unassociated
 				.iter()
 				.for_each(|(relay_account, contrib)| {
-					let reward_info = RewardInfo {
-						total_reward: BalanceOf::<T>::from(*contrib)
-							.saturating_mul(BalanceOf::<T>::from(self.reward_ratio)),
-						claimed_reward: 0u32.into(),
-						last_paid: 0u32.into(),
-					};
+					let reward_info = RewardInfo::<T>::new(self.reward_for(contrib.0));
 					UnassociatedContributions::<T>::insert(relay_account, reward_info);
 				});
+
+			if let Some(root) = self.contributions_merkle_root {
+				ContributionsMerkleRoot::<T>::put(root);
+			}
+
+			// Initialize storage for UN-associated ethereum contributions
+			self.unassociated_ethereum
+				.iter()
+				.for_each(|(ethereum_address, contrib)| {
+					let reward_info = RewardInfo::<T>::new(self.reward_for(contrib.0));
+					UnassociatedEthereumContributions::<T>::insert(ethereum_address, reward_info);
+				});
 		}
 	}
 
@@ -381,10 +1930,36 @@ pub mod pallet {
 		/// Someone has proven they made a contribution and associated a native identity with it.
 		/// Data is the relay account,  native account and the total amount of _rewards_ that will be paid
 		NativeIdentityAssociated(T::RelayChainAccountId, T::AccountId, BalanceOf<T>),
+		/// Someone has proven they made an offchain contribution from an Ethereum wallet and
+		/// associated a native identity with it.
+		/// Data is the ethereum address, native account and the total amount of _rewards_ that
+		/// will be paid
+		EthereumIdentityAssociated(H160, T::AccountId, BalanceOf<T>),
 		/// A contributor has claimed some rewards.
 		/// Data is the account getting paid and the amount of rewards paid.
 		RewardsPaid(T::AccountId, BalanceOf<T>),
+		/// A beneficiary of a batch payout could not be paid and was skipped.
+		/// Data is the beneficiary and the reason it could not be paid.
+		PayoutSkipped(T::AccountId, DispatchError),
 		/// A contributor has updated the reward address.
 		RewardAddressUpdated(T::AccountId, T::AccountId),
+		/// A contributor has proposed a new reward address, which must accept it.
+		/// Data is the current and proposed reward accounts, and the last block to accept it.
+		RewardAddressProposed(T::AccountId, T::AccountId, T::BlockNumber),
+		/// A contributor has withdrawn its proposed reward address change.
+		RewardAddressProposalCancelled(T::AccountId),
+		/// The root of the contributions Merkle tree has been set.
+		ContributionsMerkleRootSet(H256),
+		/// A chunk of contributions has been registered.
+		/// Data is the number of contributions in the chunk and the total reward registered so far.
+		RewardsInitialized(u32, BalanceOf<T>),
+		/// The set of contributions has been locked.
+		/// Data is the total reward registered.
+		InitializationCompleted(BalanceOf<T>),
+		/// The vesting schedule has been changed.
+		/// Data is the first and last block of the vesting period.
+		VestingScheduleSet(T::BlockNumber, T::BlockNumber),
+		/// The account signing automatic claims has been changed. `None` disables them.
+		AutoClaimAuthoritySet(Option<T::AccountId>),
 	}
 }
@@ -0,0 +1,72 @@
+// Copyright 2019-2021 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Merkle proofs of contributions
+//!
+//! Instead of listing every contributor in genesis, a chain can store the root of a Merkle tree
+//! whose leaves commit to `(relay account, reward)` pairs. Off-chain tree builders must hash
+//! exactly like this module:
+//!
+//! - a leaf is `blake2_256(0x00 ++ SCALE((relay_account, reward)))`
+//! - a node is `blake2_256(0x01 ++ first ++ second)`, where `first` is the smaller of its two
+//!   children
+//!
+//! The prefixes keep the two apart, so the 64 bytes of an inner node can never be passed off as a
+//! leaf. Sorting the children means a proof is simply the list of sibling hashes from the leaf up
+//! to the root and does not need to carry left/right flags. A node without a sibling is promoted
+//! to the next layer unchanged.
+
+use parity_scale_codec::Encode;
+use sp_core::H256;
+use sp_io::hashing::blake2_256;
+
+/// Proofs longer than this cannot come from a tree with less than 2^32 leaves.
+pub const MAX_PROOF_LENGTH: usize = 32;
+
+/// Prepended to the preimage of leaves.
+pub const LEAF_PREFIX: u8 = 0x00;
+
+/// Prepended to the preimage of inner nodes.
+pub const NODE_PREFIX: u8 = 0x01;
+
+/// The leaf committing to `reward` being owed to `relay_account`.
+pub fn leaf<RelayChainAccountId: Encode, Balance: Encode>(
+	relay_account: &RelayChainAccountId,
+	reward: &Balance,
+) -> H256 {
+	let mut preimage = sp_std::vec![LEAF_PREFIX];
+	(relay_account, reward).encode_to(&mut preimage);
+	blake2_256(&preimage).into()
+}
+
+/// The parent of two nodes.
+pub fn node(a: H256, b: H256) -> H256 {
+	let (first, second) = if a <= b { (a, b) } else { (b, a) };
+	let mut preimage = [0u8; 65];
+	preimage[0] = NODE_PREFIX;
+	preimage[1..33].copy_from_slice(first.as_bytes());
+	preimage[33..].copy_from_slice(second.as_bytes());
+	blake2_256(&preimage).into()
+}
+
+/// Whether `proof` shows that `leaf` is part of the tree with the given `root`.
+pub fn verify_proof(root: H256, leaf: H256, proof: &[H256]) -> bool {
+	proof.len() <= MAX_PROOF_LENGTH
+		&& proof
+			.iter()
+			.fold(leaf, |current, sibling| node(current, *sibling))
+			== root
+}
@@ -0,0 +1,167 @@
+// Copyright 2019-2021 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage migrations
+//!
+//! The layout of the pallet storage is tracked by `StorageVersion`. Chains that predate it decode
+//! it as `Releases::V1_0_0`. `on_runtime_upgrade` runs every migration between the stored version
+//! and the latest one, in order, and each migration bumps the stored version when done.
+
+use crate::pallet::{
+	AccountsPayable, BalanceOf, ClaimedEthereumAddresses, ClaimedRelayChainIds, Config,
+	Initialized, InitializedRewardAmount, Releases, RewardInfo, StorageVersion,
+	UnassociatedContributions, UnassociatedEthereumContributions,
+};
+use frame_support::{traits::Get, weights::Weight};
+use parity_scale_codec::{Decode, Encode};
+use sp_runtime::traits::{Saturating, Zero};
+use sp_runtime::RuntimeDebug;
+
+/// Moves `RewardInfo` from the original `total_reward`, `claimed_reward`, `last_paid` layout to
+/// the one carrying an `initial_payment`.
+///
+/// Rewards were paid entirely by vesting before, so migrated rewards get a zero initial payment
+/// and keep vesting exactly as they did. Those chains configured every contribution at genesis,
+/// so the set of contributions is marked complete, with its total recomputed from storage.
+///
+/// Rewards used to be minted, whereas they are now paid out of the pallet's pot. The pot must be
+/// funded with the outstanding rewards as part of the same upgrade, or payouts will fail with
+/// `InsufficientRewardPot`.
+pub mod v2 {
+	use super::*;
+
+	/// `RewardInfo` as stored in `Releases::V1_0_0`.
+	#[derive(Encode, Decode, RuntimeDebug)]
+	pub struct OldRewardInfo<Balance, BlockNumber> {
+		pub total_reward: Balance,
+		pub claimed_reward: Balance,
+		pub last_paid: BlockNumber,
+	}
+
+	fn translate<T: Config>(
+		old: OldRewardInfo<BalanceOf<T>, T::BlockNumber>,
+		total: &mut BalanceOf<T>,
+		entries: &mut u64,
+	) -> Option<RewardInfo<T>> {
+		*total = total.saturating_add(old.total_reward);
+		*entries += 1;
+		Some(RewardInfo {
+			total_reward: old.total_reward,
+			claimed_reward: old.claimed_reward,
+			last_paid: old.last_paid,
+			initial_payment: Zero::zero(),
+		})
+	}
+
+	pub fn migrate<T: Config>() -> Weight {
+		let mut total = BalanceOf::<T>::zero();
+		let mut entries = 0u64;
+
+		AccountsPayable::<T>::translate(|_, old| translate::<T>(old, &mut total, &mut entries));
+		UnassociatedContributions::<T>::translate(|_, old| {
+			translate::<T>(old, &mut total, &mut entries)
+		});
+		UnassociatedEthereumContributions::<T>::translate(|_, old| {
+			translate::<T>(old, &mut total, &mut entries)
+		});
+
+		InitializedRewardAmount::<T>::put(total);
+		Initialized::<T>::put(!entries.is_zero());
+		StorageVersion::<T>::put(Releases::V2_0_0);
+
+		T::DbWeight::get().reads_writes(entries.saturating_add(1), entries.saturating_add(3))
+	}
+
+	#[cfg(feature = "try-runtime")]
+	pub fn pre_upgrade<T: Config>() -> Result<(), &'static str> {
+		frame_support::ensure!(
+			StorageVersion::<T>::get() == Releases::V1_0_0,
+			"Rewards: storage is not at V1_0_0"
+		);
+		Ok(())
+	}
+
+	#[cfg(feature = "try-runtime")]
+	pub fn post_upgrade<T: Config>() -> Result<(), &'static str> {
+		// A layout mismatch would most likely surface as garbage amounts
+		frame_support::ensure!(
+			AccountsPayable::<T>::iter_values()
+				.chain(UnassociatedContributions::<T>::iter_values())
+				.chain(UnassociatedEthereumContributions::<T>::iter_values())
+				.all(|info| {
+					info.claimed_reward <= info.total_reward
+						&& info.initial_payment <= info.total_reward
+				}),
+			"Rewards: some RewardInfo does not decode to a consistent reward"
+		);
+		Ok(())
+	}
+}
+
+/// Makes `ClaimedRelayChainIds` and `ClaimedEthereumAddresses` hold the native account each
+/// relay account or Ethereum address is associated with, instead of `()`, and adds the
+/// `AssociatedRelayAccounts` and `AssociatedEthereumAddresses` reverse indexes.
+///
+/// Existing associations never recorded their native account, and it cannot be recovered from
+/// storage. So they are migrated to `None`, ie "associated before V3_0_0", and are not part of
+/// the reverse index. Associations made after the upgrade record their native account.
+pub mod v3 {
+	use super::*;
+
+	pub fn migrate<T: Config>() -> Weight {
+		let mut entries = 0u64;
+
+		ClaimedRelayChainIds::<T>::translate(|_, ()| {
+			entries += 1;
+			Some(None)
+		});
+		ClaimedEthereumAddresses::<T>::translate(|_, ()| {
+			entries += 1;
+			Some(None)
+		});
+		StorageVersion::<T>::put(Releases::V3_0_0);
+
+		T::DbWeight::get().reads_writes(entries, entries.saturating_add(1))
+	}
+
+	#[cfg(feature = "try-runtime")]
+	pub fn post_upgrade<T: Config>() -> Result<(), &'static str> {
+		use crate::pallet::{AssociatedEthereumAddresses, AssociatedRelayAccounts};
+
+		// Every association in the reverse index points back to its native account
+		frame_support::ensure!(
+			AssociatedRelayAccounts::<T>::iter().all(|(native_account, relay_accounts)| {
+				relay_accounts.iter().all(|relay_account| {
+					ClaimedRelayChainIds::<T>::get(relay_account)
+						.flatten()
+						.as_ref() == Some(&native_account)
+				})
+			}),
+			"Rewards: ClaimedRelayChainIds and AssociatedRelayAccounts disagree"
+		);
+		frame_support::ensure!(
+			AssociatedEthereumAddresses::<T>::iter().all(|(native_account, addresses)| {
+				addresses.iter().all(|address| {
+					ClaimedEthereumAddresses::<T>::get(address)
+						.flatten()
+						.as_ref() == Some(&native_account)
+				})
+			}),
+			"Rewards: ClaimedEthereumAddresses and AssociatedEthereumAddresses disagree"
+		);
+		Ok(())
+	}
+}
@@ -15,20 +15,29 @@
 // along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
 
 //! Test utilities
-use crate::{self as pallet_crowdloan_rewards, Config};
+use crate::{
+	self as pallet_crowdloan_rewards,
+	vesting::{Linear, PayoutMode},
+	Config,
+};
 use frame_support::{
-	construct_runtime,
-	parameter_types,
-	traits::{GenesisBuild, OnInitialize, OnFinalize}
+	construct_runtime, parameter_types,
+	traits::{GenesisBuild, Get, OnFinalize, OnInitialize},
+	weights::Weight,
+	PalletId,
 };
-use sp_core::ed25519;
+use frame_system::EnsureRoot;
 use sp_core::Pair;
-use sp_core::H256;
+use sp_core::{ecdsa, ed25519};
+use sp_core::{H160, H256};
 use sp_io;
+use sp_io::hashing::keccak_256;
 use sp_runtime::{
-	testing::Header,
-	traits::{BlakeTwo256, IdentityLookup},
+	testing::{Header, TestSignature, TestXt, UintAuthorityId},
+	traits::{BlakeTwo256, ConvertInto, IdentityLookup},
+	FixedPointNumber, FixedU128, MultiSignature, Perbill,
 };
+use sp_std::cell::RefCell;
 use sp_std::convert::From;
 use sp_std::convert::TryInto;
 
@@ -38,7 +47,6 @@ pub type Balance = u128;
 type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
 type Block = frame_system::mocking::MockBlock<Test>;
 
-
 construct_runtime!(
 	pub enum Test where
 		Block = Block,
@@ -47,7 +55,8 @@ construct_runtime!(
 	{
 		System: frame_system::{Pallet, Call, Config, Storage, Event<T>},
 		Balances: pallet_balances::{Pallet, Call, Storage, Config<T>, Event<T>},
-		Crowdloan: pallet_crowdloan_rewards::{Pallet, Call, Storage, Config<T>, Event<T>},
+		Vesting: pallet_vesting::{Pallet, Call, Storage, Event<T>},
+		Crowdloan: pallet_crowdloan_rewards::{Pallet, Call, Storage, Config<T>, Event<T>, ValidateUnsigned},
 	}
 );
 
@@ -96,8 +105,103 @@ impl pallet_balances::Config for Test {
 	type WeightInfo = ();
 }
 
+parameter_types! {
+	pub const MinVestedTransfer: Balance = 1;
+}
+
+impl pallet_vesting::Config for Test {
+	type Event = Event;
+	type Currency = Balances;
+	type BlockNumberToBalance = ConvertInto;
+	type MinVestedTransfer = MinVestedTransfer;
+	type WeightInfo = ();
+}
+
 parameter_types! {
 	pub const TestVestingPeriod: u64 = 8;
+	pub const CrowdloanPalletId: PalletId = PalletId(*b"Crowdloa");
+	pub const TestUnsignedPriority: u64 = 100;
+	pub const TestMaxInitContributors: u32 = 4;
+	pub const TestMaxBatchPayouts: u32 = 4;
+	pub const TestAutoClaimThreshold: Balance = 100;
+	pub const TestRewardAddressProposalExpiry: u64 = 10;
+	pub const TestMaxRelayAccountsPerAccount: u32 = 4;
+	pub const TestSignatureNetworkIdentifier: &'static [u8] = b"test-";
+}
+
+thread_local! {
+	static INITIAL_PAYMENT: RefCell<Perbill> = RefCell::new(Perbill::zero());
+	static AUTO_PAYOUTS_PER_BLOCK: RefCell<u32> = RefCell::new(0);
+	static AUTO_PAYOUT_WEIGHT_BUDGET: RefCell<Weight> = RefCell::new(Weight::max_value());
+	static PAYOUT_MODE: RefCell<PayoutMode> = RefCell::new(PayoutMode::Claim);
+}
+
+pub struct TestInitialPayment;
+impl Get<Perbill> for TestInitialPayment {
+	fn get() -> Perbill {
+		INITIAL_PAYMENT.with(|v| *v.borrow())
+	}
+}
+
+/// Must be called before building genesis, which is where the initial payment is split out.
+pub(crate) fn set_initial_payment(initial_payment: Perbill) {
+	INITIAL_PAYMENT.with(|v| *v.borrow_mut() = initial_payment);
+}
+
+pub struct TestAutoPayoutsPerBlock;
+impl Get<u32> for TestAutoPayoutsPerBlock {
+	fn get() -> u32 {
+		AUTO_PAYOUTS_PER_BLOCK.with(|v| *v.borrow())
+	}
+}
+
+pub struct TestAutoPayoutWeightBudget;
+impl Get<Weight> for TestAutoPayoutWeightBudget {
+	fn get() -> Weight {
+		AUTO_PAYOUT_WEIGHT_BUDGET.with(|v| *v.borrow())
+	}
+}
+
+/// Automatic payouts are disabled unless a test enables them.
+pub(crate) fn set_auto_payouts(per_block: u32, weight_budget: Weight) {
+	AUTO_PAYOUTS_PER_BLOCK.with(|v| *v.borrow_mut() = per_block);
+	AUTO_PAYOUT_WEIGHT_BUDGET.with(|v| *v.borrow_mut() = weight_budget);
+}
+
+pub struct TestPayoutMode;
+impl Get<PayoutMode> for TestPayoutMode {
+	fn get() -> PayoutMode {
+		PAYOUT_MODE.with(|v| *v.borrow())
+	}
+}
+
+/// Rewards are claimed as they vest unless a test switches to `PayoutMode::Lock`.
+pub(crate) fn set_payout_mode(payout_mode: PayoutMode) {
+	PAYOUT_MODE.with(|v| *v.borrow_mut() = payout_mode);
+}
+
+impl frame_system::offchain::SigningTypes for Test {
+	type Public = UintAuthorityId;
+	type Signature = TestSignature;
+}
+
+impl<LocalCall> frame_system::offchain::SendTransactionTypes<LocalCall> for Test
+where
+	Call: From<LocalCall>,
+{
+	type OverarchingCall = Call;
+	type Extrinsic = Extrinsic;
+}
+
+pub type Extrinsic = TestXt<Call, ()>;
+
+/// Signs automatic claims with the `UintAuthorityId` keys set by `UintAuthorityId::set_all_keys`,
+/// whose account is the key itself.
+pub struct TestAuthId;
+impl frame_system::offchain::AppCrypto<UintAuthorityId, TestSignature> for TestAuthId {
+	type RuntimeAppPublic = UintAuthorityId;
+	type GenericSignature = TestSignature;
+	type GenericPublic = UintAuthorityId;
 }
 
 impl Config for Test {
@@ -105,29 +209,80 @@ impl Config for Test {
 	type RewardCurrency = Balances;
 	type RelayChainAccountId = [u8; 32];
 	type VestingPeriod = TestVestingPeriod;
+	type SignatureNetworkIdentifier = TestSignatureNetworkIdentifier;
+	type UnsignedPriority = TestUnsignedPriority;
+	type VestingCurve = Linear;
+	type InitializationOrigin = EnsureRoot<AccountId>;
+	type MaxInitContributors = TestMaxInitContributors;
+	type InitialPayment = TestInitialPayment;
+	type PalletId = CrowdloanPalletId;
+	type MaxBatchPayouts = TestMaxBatchPayouts;
+	type AutoPayoutsPerBlock = TestAutoPayoutsPerBlock;
+	type AutoPayoutWeightBudget = TestAutoPayoutWeightBudget;
+	type AuthorityId = TestAuthId;
+	type AutoClaimThreshold = TestAutoClaimThreshold;
+	type PayoutMode = TestPayoutMode;
+	type VestingSchedule = Vesting;
+	type RewardAddressProposalExpiry = TestRewardAddressProposalExpiry;
+	type MaxRelayAccountsPerAccount = TestMaxRelayAccountsPerAccount;
+	type WeightInfo = ();
 }
 
-fn genesis(
-	assigned: Vec<([u8; 32], AccountId, u32)>,
-	unassigned: Vec<([u8; 32], u32)>,
+pub(crate) fn genesis(
+	assigned: Vec<([u8; 32], AccountId, u128)>,
+	unassigned: Vec<([u8; 32], u128)>,
+	reward_ratio: FixedU128,
+	reward_pot: Balance,
+) -> sp_io::TestExternalities {
+	genesis_with_config(
+		pallet_crowdloan_rewards::GenesisConfig::<Test> {
+			associated: assigned
+				.into_iter()
+				.map(|(relay, native, contrib)| (relay, native, contrib.into()))
+				.collect(),
+			unassociated: unassigned
+				.into_iter()
+				.map(|(relay, contrib)| (relay, contrib.into()))
+				.collect(),
+			reward_ratio,
+			..Default::default()
+		},
+		reward_pot,
+	)
+}
+
+pub(crate) fn genesis_with_config(
+	config: pallet_crowdloan_rewards::GenesisConfig<Test>,
+	reward_pot: Balance,
 ) -> sp_io::TestExternalities {
 	let mut storage = frame_system::GenesisConfig::default()
 		.build_storage::<Test>()
 		.unwrap();
 
-	pallet_crowdloan_rewards::GenesisConfig::<Test> {
-		associated: assigned,
-		unassociated: unassigned,
-		reward_ratio: 1,
+	pallet_balances::GenesisConfig::<Test> {
+		balances: vec![(Crowdloan::account_id(), reward_pot)],
 	}
 	.assimilate_storage(&mut storage)
 	.unwrap();
 
+	config.assimilate_storage(&mut storage).unwrap();
+
 	let mut ext = sp_io::TestExternalities::from(storage);
 	ext.execute_with(|| System::set_block_number(1));
 	ext
 }
 
+/// Empty externalities with a keystore, in which the benchmarks generate their relay chain keys.
+#[cfg(feature = "runtime-benchmarks")]
+pub(crate) fn benchmarks_ext() -> sp_io::TestExternalities {
+	use sp_keystore::{testing::KeyStore, KeystoreExt};
+	use std::sync::Arc;
+
+	let mut ext = genesis_with_config(Default::default(), 1);
+	ext.register_extension(KeystoreExt(Arc::new(KeyStore::new())));
+	ext
+}
+
 pub(crate) fn get_ed25519_pairs(num: u32) -> Vec<ed25519::Pair> {
 	let seed: u128 = 12345678901234567890123456789012;
 	let mut pairs = Vec::new();
@@ -143,7 +298,73 @@ pub(crate) fn get_ed25519_pairs(num: u32) -> Vec<ed25519::Pair> {
 	pairs
 }
 
+/// Signs the association payload for `reward_account`. The payload depends on the genesis hash, so
+/// this must be called from within the externalities.
+pub(crate) fn association_signature(
+	pair: &ed25519::Pair,
+	reward_account: AccountId,
+) -> MultiSignature {
+	pair.sign(&Crowdloan::association_payload(&reward_account))
+		.into()
+}
+
+pub(crate) fn ethereum_secret(seed: &[u8]) -> secp256k1::SecretKey {
+	secp256k1::SecretKey::parse(&keccak_256(seed)).unwrap()
+}
+
+pub(crate) fn ethereum_address(secret: &secp256k1::SecretKey) -> H160 {
+	let public = secp256k1::PublicKey::from_secret_key(secret).serialize();
+	H160::from_slice(&keccak_256(&public[1..65])[12..])
+}
+
+/// What an Ethereum wallet produces when asked to `eth_sign` the association message for
+/// `reward_account`. Must be called from within the externalities.
+pub(crate) fn ethereum_association_signature(
+	secret: &secp256k1::SecretKey,
+	reward_account: AccountId,
+) -> ecdsa::Signature {
+	let message = Crowdloan::association_message(&reward_account);
+	let digest = keccak_256(&Crowdloan::ethereum_signable_message(&message));
+	let (signature, recovery_id) = secp256k1::sign(&secp256k1::Message::parse(&digest), secret);
+	let mut raw = [0u8; 65];
+	raw[0..64].copy_from_slice(&signature.serialize()[..]);
+	// Wallets report the recovery id as 27 or 28
+	raw[64] = recovery_id.serialize() + 27;
+	ecdsa::Signature::from_raw(raw)
+}
+
+/// Builds the contributions Merkle tree out of `leaves`, returning the root and the proof for each
+/// leaf in order.
+pub(crate) fn merkle_tree(leaves: Vec<H256>) -> (H256, Vec<Vec<H256>>) {
+	let mut proofs = vec![Vec::new(); leaves.len()];
+	// Where each leaf's ancestor sits in the current layer
+	let mut positions: Vec<usize> = (0..leaves.len()).collect();
+	let mut layer = leaves;
+	while layer.len() > 1 {
+		for (leaf, position) in positions.iter_mut().enumerate() {
+			if let Some(sibling) = layer.get(*position ^ 1) {
+				proofs[leaf].push(*sibling);
+			}
+			*position /= 2;
+		}
+		layer = layer
+			.chunks(2)
+			.map(|pair| match pair {
+				[a, b] => crate::merkle::node(*a, *b),
+				_ => pair[0],
+			})
+			.collect();
+	}
+	(layer[0], proofs)
+}
+
 pub(crate) fn two_assigned_three_unassigned() -> sp_io::TestExternalities {
+	two_assigned_three_unassigned_with_pot(2500)
+}
+
+pub(crate) fn two_assigned_three_unassigned_with_pot(
+	reward_pot: Balance,
+) -> sp_io::TestExternalities {
 	let pairs = get_ed25519_pairs(3);
 	genesis(
 		vec![
@@ -157,6 +378,8 @@ pub(crate) fn two_assigned_three_unassigned() -> sp_io::TestExternalities {
 			(pairs[1].public().into(), 500),
 			(pairs[2].public().into(), 500),
 		],
+		FixedU128::saturating_from_integer(1u128),
+		reward_pot,
 	)
 }
 
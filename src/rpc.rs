@@ -0,0 +1,190 @@
+// Copyright 2019-2021 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! JSON-RPC interface to the reward state
+//!
+//! A typed wrapper around the `CrowdloanRewardsApi` runtime API, so explorers and bots do not
+//! need to read raw storage. Every method takes an optional block hash and defaults to the best
+//! block. Balances are returned as `NumberOrHex` because rewards routinely exceed what JavaScript
+//! numbers can represent.
+//!
+//! Node only, behind the `rpc` feature.
+
+use crate::runtime_api::CrowdloanRewardsApi as CrowdloanRewardsRuntimeApi;
+use crate::{BalanceOf, Config, RewardInfo};
+use jsonrpc_core::{Error as RpcError, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+use parity_scale_codec::Codec;
+use serde::{Deserialize, Serialize};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_rpc::number::NumberOrHex;
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor},
+};
+use std::{
+	convert::{TryFrom, TryInto},
+	fmt::Debug,
+	marker::PhantomData,
+	sync::Arc,
+};
+
+/// The rewards owed to an account as returned over RPC. The same as `RewardInfo`, with the
+/// balances as `NumberOrHex`.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RewardInfoResponse<BlockNumber> {
+	pub total_reward: NumberOrHex,
+	pub claimed_reward: NumberOrHex,
+	pub last_paid: BlockNumber,
+	pub initial_payment: NumberOrHex,
+}
+
+impl<T: Config> TryFrom<RewardInfo<T>> for RewardInfoResponse<T::BlockNumber>
+where
+	BalanceOf<T>: TryInto<NumberOrHex>,
+{
+	type Error = ();
+
+	fn try_from(info: RewardInfo<T>) -> std::result::Result<Self, ()> {
+		Ok(RewardInfoResponse {
+			total_reward: info.total_reward.try_into().map_err(|_| ())?,
+			claimed_reward: info.claimed_reward.try_into().map_err(|_| ())?,
+			last_paid: info.last_paid,
+			initial_payment: info.initial_payment.try_into().map_err(|_| ())?,
+		})
+	}
+}
+
+/// Crowdloan rewards RPC methods.
+#[rpc]
+pub trait CrowdloanRewardsApi<BlockHash, BlockNumber, AccountId, RelayChainAccountId> {
+	/// What `account` could claim with `show_me_the_money` at the given block.
+	#[rpc(name = "crowdloanRewards_claimable")]
+	fn claimable(&self, account: AccountId, at: Option<BlockHash>) -> Result<NumberOrHex>;
+
+	/// The rewards owed to `account` at the given block, mirroring `AccountsPayable`.
+	#[rpc(name = "crowdloanRewards_rewardInfo")]
+	fn reward_info(
+		&self,
+		account: AccountId,
+		at: Option<BlockHash>,
+	) -> Result<Option<RewardInfoResponse<BlockNumber>>>;
+
+	/// Whether `relay_account` had been associated with a native account at the given block.
+	#[rpc(name = "crowdloanRewards_isAssociated")]
+	fn is_associated(
+		&self,
+		relay_account: RelayChainAccountId,
+		at: Option<BlockHash>,
+	) -> Result<bool>;
+}
+
+/// Error code returned when the runtime API call fails.
+const RUNTIME_ERROR: i64 = 1;
+
+fn runtime_error(message: &str, error: impl Debug) -> RpcError {
+	RpcError {
+		code: ErrorCode::ServerError(RUNTIME_ERROR),
+		message: message.into(),
+		data: Some(format!("{:?}", error).into()),
+	}
+}
+
+fn overflow_error(message: &str) -> RpcError {
+	RpcError {
+		code: ErrorCode::InvalidParams,
+		message: message.into(),
+		data: None,
+	}
+}
+
+/// Implements the `CrowdloanRewardsApi` RPC on top of a client exposing the runtime API.
+///
+/// The runtime's `Balance` type cannot be inferred from the RPC methods, so it is a type parameter
+/// here, eg `CrowdloanRewards::<_, Block, Balance>::new(client.clone())`.
+pub struct CrowdloanRewards<C, Block, Balance> {
+	client: Arc<C>,
+	_marker: PhantomData<(Block, Balance)>,
+}
+
+impl<C, Block, Balance> CrowdloanRewards<C, Block, Balance> {
+	/// Create a new `CrowdloanRewards` RPC handler.
+	pub fn new(client: Arc<C>) -> Self {
+		CrowdloanRewards {
+			client,
+			_marker: Default::default(),
+		}
+	}
+}
+
+impl<C, Block, AccountId, RelayChainAccountId, Balance, RewardInfo>
+	CrowdloanRewardsApi<<Block as BlockT>::Hash, NumberFor<Block>, AccountId, RelayChainAccountId>
+	for CrowdloanRewards<C, Block, Balance>
+where
+	Block: BlockT,
+	C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+	C::Api: CrowdloanRewardsRuntimeApi<Block, AccountId, RelayChainAccountId, Balance, RewardInfo>,
+	AccountId: Codec,
+	RelayChainAccountId: Codec,
+	Balance: Codec + TryInto<NumberOrHex> + Send + Sync + 'static,
+	RewardInfo: Codec + TryInto<RewardInfoResponse<NumberFor<Block>>>,
+{
+	fn claimable(
+		&self,
+		account: AccountId,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<NumberOrHex> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		let claimable = api
+			.claimable(&at, account)
+			.map_err(|e| runtime_error("Unable to query claimable rewards.", e))?;
+		claimable
+			.try_into()
+			.map_err(|_| overflow_error("Claimable rewards do not fit in a NumberOrHex"))
+	}
+
+	fn reward_info(
+		&self,
+		account: AccountId,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Option<RewardInfoResponse<NumberFor<Block>>>> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		let info = api
+			.reward_info(&at, account)
+			.map_err(|e| runtime_error("Unable to query reward info.", e))?;
+		info.map(TryInto::try_into)
+			.transpose()
+			.map_err(|_| overflow_error("Rewards do not fit in a NumberOrHex"))
+	}
+
+	fn is_associated(
+		&self,
+		relay_account: RelayChainAccountId,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<bool> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		api.is_associated(&at, relay_account)
+			.map_err(|e| runtime_error("Unable to query association.", e))
+	}
+}
@@ -0,0 +1,50 @@
+// Copyright 2019-2021 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runtime API for querying reward state
+//!
+//! Lets wallets and frontends display what a contributor can claim without submitting a
+//! transaction or replicating the vesting math. Runtimes implement it by forwarding to the
+//! pallet's `claimable_for`, `vested_for`, `accounts_payable`, `unassociated_contributions` and
+//! `claimed_relay_chain_ids`, with `RewardInfo` being
+//! `pallet_crowdloan_rewards::RewardInfo<Runtime>`.
+
+#![allow(clippy::too_many_arguments)]
+#![allow(clippy::unnecessary_mut_passed)]
+
+use parity_scale_codec::Codec;
+
+sp_api::decl_runtime_apis! {
+	/// The API to query crowdloan rewards.
+	pub trait CrowdloanRewardsApi<AccountId, RelayChainAccountId, Balance, RewardInfo> where
+		AccountId: Codec,
+		RelayChainAccountId: Codec,
+		Balance: Codec,
+		RewardInfo: Codec,
+	{
+		/// What `account` could claim with `show_me_the_money` in the current block. In
+		/// `PayoutMode::Lock`, this includes the part that would be locked.
+		fn claimable(account: AccountId) -> Balance;
+		/// How much of the rewards of `account` are vested in the current block, claimed or not.
+		fn vested(account: AccountId) -> Balance;
+		/// The rewards owed to `account`, if any.
+		fn reward_info(account: AccountId) -> Option<RewardInfo>;
+		/// The contribution of `relay_account` that is waiting to be associated, if any.
+		fn unassociated(relay_account: RelayChainAccountId) -> Option<RewardInfo>;
+		/// Whether `relay_account` has already been associated with a native account.
+		fn is_associated(relay_account: RelayChainAccountId) -> bool;
+	}
+}
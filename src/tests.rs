@@ -15,12 +15,20 @@
 // along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
 
 //! Unit testing
+use crate::vesting::*;
 use crate::*;
-use frame_support::{assert_noop, assert_ok};
+use frame_support::storage::unhashed;
+use frame_support::traits::{Currency, OffchainWorker, OnRuntimeUpgrade, VestingSchedule};
+use frame_support::weights::Weight;
+use frame_support::{assert_noop, assert_ok, parameter_types, unsigned::ValidateUnsigned};
 use mock::*;
-use parity_scale_codec::Encode;
+use parity_scale_codec::{Decode, Encode};
+use sp_core::offchain::{testing::TestTransactionPoolExt, TransactionPoolExt};
 use sp_core::Pair;
-use sp_runtime::MultiSignature;
+use sp_runtime::testing::{TestSignature, UintAuthorityId};
+use sp_runtime::traits::BadOrigin;
+use sp_runtime::transaction_validity::{InvalidTransaction, TransactionSource};
+use sp_runtime::{FixedPointNumber, FixedU128, MultiSignature, Perbill};
 #[test]
 fn geneses() {
 	let pairs = get_ed25519_pairs(3);
@@ -51,8 +59,8 @@ fn geneses() {
 #[test]
 fn proving_assignation_works() {
 	let pairs = get_ed25519_pairs(3);
-	let signature: MultiSignature = pairs[0].sign(&3u64.encode()).into();
 	two_assigned_three_unassigned().execute_with(|| {
+		let signature = association_signature(&pairs[0], 3);
 		// 4 is not payable first
 		assert!(Crowdloan::accounts_payable(&3).is_none());
 		roll_to(4);
@@ -98,68 +106,204 @@ fn proving_assignation_works() {
 }
 
 #[test]
-fn paying_works() {
+fn unsigned_association_works() {
+	let pairs = get_ed25519_pairs(3);
+	let relay_account: [u8; 32] = pairs[0].public().into();
 	two_assigned_three_unassigned().execute_with(|| {
-		// 1 is payable
-		assert!(Crowdloan::accounts_payable(&1).is_some());
-		roll_to(4);
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
-		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().last_paid, 4u64);
-		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 248);
-		assert_noop!(
-			Crowdloan::show_me_the_money(Origin::signed(3)),
-			Error::<Test>::NoAssociatedClaim
+		let signature = association_signature(&pairs[0], 3);
+		let call = crate::Call::<Test>::associate_native_identity_unsigned(
+			3,
+			relay_account,
+			signature.clone(),
 		);
-		roll_to(5);
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
-		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().last_paid, 5u64);
-		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 310);
-		roll_to(6);
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
-		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().last_paid, 6u64);
-		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 372);
-		roll_to(7);
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
-		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().last_paid, 7u64);
-		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 434);
-		roll_to(230);
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
-		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 500);
-		roll_to(330);
+		let valid =
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call)
+				.unwrap();
+		assert_eq!(valid.priority, 100);
+		assert_eq!(
+			valid.provides,
+			vec![("CrowdloanRewardsAssociation", relay_account).encode()]
+		);
+
+		// Only unsigned origins are accepted
 		assert_noop!(
-			Crowdloan::show_me_the_money(Origin::signed(1)),
-			Error::<Test>::RewardsAlreadyClaimed
+			Crowdloan::associate_native_identity_unsigned(
+				Origin::signed(4),
+				3,
+				relay_account,
+				signature.clone()
+			),
+			BadOrigin
 		);
+		assert_ok!(Crowdloan::associate_native_identity_unsigned(
+			Origin::none(),
+			3,
+			relay_account,
+			signature
+		));
+		assert!(Crowdloan::accounts_payable(&3).is_some());
 
-		let expected = vec![
-			crate::Event::RewardsPaid(1, 248),
-			crate::Event::RewardsPaid(1, 62),
-			crate::Event::RewardsPaid(1, 62),
-			crate::Event::RewardsPaid(1, 62),
-			crate::Event::RewardsPaid(1, 66),
-		];
-		assert_eq!(events(), expected);
+		// Once associated, the same transaction is stale
+		assert_eq!(
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call),
+			Err(InvalidTransaction::Stale.into())
+		);
 	});
 }
 
 #[test]
-fn paying_late_joiner_works() {
+fn unsigned_association_is_validated() {
+	let pairs = get_ed25519_pairs(3);
+	two_assigned_three_unassigned().execute_with(|| {
+		let signature = association_signature(&pairs[0], 3);
+		// Signature over another account
+		let call = crate::Call::<Test>::associate_native_identity_unsigned(
+			4,
+			pairs[0].public().into(),
+			signature.clone(),
+		);
+		assert_eq!(
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call),
+			Err(InvalidTransaction::BadProof.into())
+		);
+
+		// Valid signature, but no contribution to associate
+		let stranger = get_ed25519_pairs(4).pop().unwrap();
+		let call = crate::Call::<Test>::associate_native_identity_unsigned(
+			3,
+			stranger.public().into(),
+			association_signature(&stranger, 3),
+		);
+		assert_eq!(
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call),
+			Err(InvalidTransaction::Custom(ValidityError::NoUnassociatedContribution as u8).into())
+		);
+
+		// Other calls are never valid unsigned
+		let call = crate::Call::<Test>::show_me_the_money();
+		assert_eq!(
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call),
+			Err(InvalidTransaction::Call.into())
+		);
+	});
+}
+
+#[test]
+fn association_payload_is_domain_separated() {
+	two_assigned_three_unassigned().execute_with(|| {
+		let mut expected = b"<Bytes>test-".to_vec();
+		expected.extend_from_slice(System::block_hash(0).as_ref());
+		expected.extend_from_slice(b"Crowdloan");
+		expected.extend_from_slice(&3u64.encode());
+		expected.extend_from_slice(b"</Bytes>");
+		assert_eq!(Crowdloan::association_payload(&3), expected);
+	});
+}
+
+#[test]
+fn bare_account_signature_is_rejected() {
 	let pairs = get_ed25519_pairs(3);
+	// The pre-domain-separation payload could have been signed for anything
 	let signature: MultiSignature = pairs[0].sign(&3u64.encode()).into();
 	two_assigned_three_unassigned().execute_with(|| {
-		//
-		roll_to(12);
-		assert_ok!(Crowdloan::associate_native_identity(
-			Origin::signed(4),
+		assert_noop!(
+			Crowdloan::associate_native_identity(
+				Origin::signed(4),
+				3,
+				pairs[0].public().into(),
+				signature
+			),
+			Error::<Test>::InvalidClaimSignature
+		);
+	});
+}
+
+#[test]
+fn association_signature_cannot_be_replayed_on_another_chain() {
+	let pairs = get_ed25519_pairs(3);
+	// Sign on a chain with a different genesis hash
+	let signature = two_assigned_three_unassigned().execute_with(|| {
+		frame_system::BlockHash::<Test>::insert(0, sp_core::H256::repeat_byte(42));
+		association_signature(&pairs[0], 3)
+	});
+	two_assigned_three_unassigned().execute_with(|| {
+		assert_noop!(
+			Crowdloan::associate_native_identity(
+				Origin::signed(4),
+				3,
+				pairs[0].public().into(),
+				signature.clone()
+			),
+			Error::<Test>::InvalidClaimSignature
+		);
+		let call = crate::Call::<Test>::associate_native_identity_unsigned(
 			3,
 			pairs[0].public().into(),
+			signature,
+		);
+		assert_eq!(
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call),
+			Err(InvalidTransaction::BadProof.into())
+		);
+	});
+}
+
+fn ethereum_genesis(contributions: Vec<(sp_core::H160, u128)>) -> sp_io::TestExternalities {
+	genesis_with_config(
+		GenesisConfig::<Test> {
+			unassociated_ethereum: contributions
+				.into_iter()
+				.map(|(address, contrib)| (address, contrib.into()))
+				.collect(),
+			reward_ratio: FixedU128::saturating_from_integer(1u128),
+			..Default::default()
+		},
+		1000,
+	)
+}
+
+#[test]
+fn ethereum_association_works() {
+	let secret = ethereum_secret(b"contributor");
+	let address = ethereum_address(&secret);
+	ethereum_genesis(vec![(address, 500)]).execute_with(|| {
+		assert!(Crowdloan::unassociated_ethereum_contributions(&address).is_some());
+		let signature = ethereum_association_signature(&secret, 3);
+		let call = crate::Call::<Test>::associate_ethereum_identity(3, address, signature.clone());
+		let valid =
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call)
+				.unwrap();
+		assert_eq!(
+			valid.provides,
+			vec![("CrowdloanRewardsAssociation", address).encode()]
+		);
+
+		assert_ok!(Crowdloan::associate_ethereum_identity(
+			Origin::none(),
+			3,
+			address,
 			signature.clone()
 		));
+		assert_eq!(Crowdloan::accounts_payable(&3).unwrap().total_reward, 500);
+		assert!(Crowdloan::unassociated_ethereum_contributions(&address).is_none());
+		assert_eq!(
+			Crowdloan::claimed_ethereum_addresses(&address),
+			Some(Some(3))
+		);
+		assert_eq!(Crowdloan::associated_ethereum_addresses(&3), vec![address]);
+
+		// Associating twice is not possible
+		assert_noop!(
+			Crowdloan::associate_ethereum_identity(Origin::none(), 3, address, signature),
+			Error::<Test>::AlreadyAssociated
+		);
+
+		roll_to(8);
 		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
-		assert_eq!(Crowdloan::accounts_payable(&3).unwrap().last_paid, 12u64);
-		assert_eq!(Crowdloan::accounts_payable(&3).unwrap().claimed_reward, 500);
+		assert_eq!(Balances::free_balance(&3), 500);
+
 		let expected = vec![
-			crate::Event::NativeIdentityAssociated(pairs[0].public().into(), 3, 500),
+			crate::Event::EthereumIdentityAssociated(address, 3, 500),
 			crate::Event::RewardsPaid(3, 500),
 		];
 		assert_eq!(events(), expected);
@@ -167,53 +311,1952 @@ fn paying_late_joiner_works() {
 }
 
 #[test]
-fn update_address_works() {
-	two_assigned_three_unassigned().execute_with(|| {
-		roll_to(4);
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+fn ethereum_associations_are_bounded_and_moved() {
+	let secrets = [ethereum_secret(b"first"), ethereum_secret(b"second")];
+	let addresses = [ethereum_address(&secrets[0]), ethereum_address(&secrets[1])];
+	genesis_with_config(
+		GenesisConfig::<Test> {
+			associated: (1u8..=3).map(|i| ([i; 32], 1, 500.into())).collect(),
+			unassociated_ethereum: addresses.iter().map(|a| (*a, 500.into())).collect(),
+			reward_ratio: FixedU128::saturating_from_integer(1u128),
+			..Default::default()
+		},
+		2500,
+	)
+	.execute_with(|| {
+		let associate = |index: usize, account: AccountId| {
+			Crowdloan::associate_ethereum_identity(
+				Origin::none(),
+				account,
+				addresses[index],
+				ethereum_association_signature(&secrets[index], account),
+			)
+		};
+		assert_ok!(associate(0, 1));
+		assert_eq!(
+			Crowdloan::associated_ethereum_addresses(&1),
+			vec![addresses[0]]
+		);
+
+		// Ethereum addresses count toward the bound along with relay accounts
+		let call = crate::Call::<Test>::associate_ethereum_identity(
+			1,
+			addresses[1],
+			ethereum_association_signature(&secrets[1], 1),
+		);
+		assert_eq!(
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call),
+			Err(InvalidTransaction::ExhaustsResources.into())
+		);
+		assert_noop!(associate(1, 1), Error::<Test>::TooManyRelayAccounts);
+
+		// Moving a reward moves its Ethereum contributions along
+		assert_ok!(Crowdloan::update_reward_address(Origin::signed(1), 3));
+		assert_eq!(
+			Crowdloan::claimed_ethereum_addresses(&addresses[0]),
+			Some(Some(3))
+		);
+		assert_eq!(
+			Crowdloan::associated_ethereum_addresses(&3),
+			vec![addresses[0]]
+		);
+		assert!(Crowdloan::associated_ethereum_addresses(&1).is_empty());
+		assert_ok!(associate(1, 1));
+	});
+}
+
+#[test]
+fn ethereum_association_checks_the_signer() {
+	let secret = ethereum_secret(b"contributor");
+	let address = ethereum_address(&secret);
+	let impostor = ethereum_secret(b"impostor");
+	ethereum_genesis(vec![(address, 500)]).execute_with(|| {
+		// Signed by somebody else
 		assert_noop!(
-			Crowdloan::show_me_the_money(Origin::signed(8)),
+			Crowdloan::associate_ethereum_identity(
+				Origin::none(),
+				3,
+				address,
+				ethereum_association_signature(&impostor, 3)
+			),
+			Error::<Test>::InvalidClaimSignature
+		);
+		// Signed for another reward account
+		let call = crate::Call::<Test>::associate_ethereum_identity(
+			4,
+			address,
+			ethereum_association_signature(&secret, 3),
+		);
+		assert_eq!(
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call),
+			Err(InvalidTransaction::BadProof.into())
+		);
+		// The impostor has no contribution of its own
+		assert_noop!(
+			Crowdloan::associate_ethereum_identity(
+				Origin::none(),
+				3,
+				ethereum_address(&impostor),
+				ethereum_association_signature(&impostor, 3)
+			),
 			Error::<Test>::NoAssociatedClaim
 		);
-		assert_ok!(Crowdloan::update_reward_address(Origin::signed(1), 8));
-		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().last_paid, 4u64);
-		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().claimed_reward, 248);
-		roll_to(6);
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(8)));
-		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().last_paid, 6u64);
-		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().claimed_reward, 372);
-		let expected = vec![
-			crate::Event::RewardsPaid(1, 248),
-			crate::Event::RewardAddressUpdated(1, 8),
-			crate::Event::RewardsPaid(8, 124),
-		];
-		assert_eq!(events(), expected);
+		// Ethereum associations are unsigned only
+		assert_noop!(
+			Crowdloan::associate_ethereum_identity(
+				Origin::signed(3),
+				3,
+				address,
+				ethereum_association_signature(&secret, 3)
+			),
+			BadOrigin
+		);
 	});
 }
 
 #[test]
-fn update_address_with_existing_address_works() {
-	two_assigned_three_unassigned().execute_with(|| {
+fn ethereum_signable_message_follows_eip_191() {
+	assert_eq!(
+		Crowdloan::ethereum_signable_message(b"hello"),
+		b"\x19Ethereum Signed Message:\n5hello".to_vec()
+	);
+	let long = [7u8; 123];
+	let mut expected = b"\x19Ethereum Signed Message:\n123".to_vec();
+	expected.extend_from_slice(&long);
+	assert_eq!(Crowdloan::ethereum_signable_message(&long), expected);
+}
+
+#[test]
+fn merkle_leaves_and_nodes_are_domain_separated() {
+	use sp_io::hashing::blake2_256;
+	let relay_account = [1u8; 32];
+	let leaf = crate::merkle::leaf(&relay_account, &500u128);
+	let mut preimage = vec![0x00];
+	preimage.extend((relay_account, 500u128).encode());
+	assert_eq!(leaf, sp_core::H256::from(blake2_256(&preimage)));
+
+	let other = crate::merkle::leaf(&[2u8; 32], &300u128);
+	let (first, second) = if leaf < other {
+		(leaf, other)
+	} else {
+		(other, leaf)
+	};
+	let mut preimage = vec![0x01];
+	preimage.extend(first.as_bytes());
+	preimage.extend(second.as_bytes());
+	assert_eq!(
+		crate::merkle::node(leaf, other),
+		sp_core::H256::from(blake2_256(&preimage))
+	);
+	assert_eq!(
+		crate::merkle::node(other, leaf),
+		crate::merkle::node(leaf, other)
+	);
+}
+
+#[test]
+fn merkle_association_works() {
+	let pairs = get_ed25519_pairs(5);
+	let leaves = pairs
+		.iter()
+		.enumerate()
+		.map(|(i, pair)| {
+			let relay_account: [u8; 32] = pair.public().into();
+			crate::merkle::leaf(&relay_account, &(100u128 * (i as u128 + 1)))
+		})
+		.collect::<Vec<_>>();
+	let (root, proofs) = merkle_tree(leaves);
+	genesis_with_config(
+		GenesisConfig::<Test> {
+			contributions_merkle_root: Some(root),
+			..Default::default()
+		},
+		1000,
+	)
+	.execute_with(|| {
+		assert_eq!(Crowdloan::contributions_merkle_root(), Some(root));
+		// The fifth leaf is promoted without a sibling on the first layer
+		for i in [1usize, 4].iter() {
+			let relay_account: [u8; 32] = pairs[*i].public().into();
+			let reward = 100 * (*i as u128 + 1);
+			let signature = association_signature(&pairs[*i], 10 + *i as u64);
+			let call = crate::Call::<Test>::associate_native_identity_with_proof(
+				10 + *i as u64,
+				relay_account,
+				reward,
+				signature.clone(),
+				proofs[*i].clone(),
+			);
+			assert!(<Crowdloan as ValidateUnsigned>::validate_unsigned(
+				TransactionSource::External,
+				&call
+			)
+			.is_ok());
+			assert_ok!(Crowdloan::associate_native_identity_with_proof(
+				Origin::none(),
+				10 + *i as u64,
+				relay_account,
+				reward,
+				signature.clone(),
+				proofs[*i].clone()
+			));
+			assert_eq!(
+				Crowdloan::accounts_payable(10 + *i as u64)
+					.unwrap()
+					.total_reward,
+				reward
+			);
+			assert!(Crowdloan::claimed_relay_chain_ids(&relay_account).is_some());
+
+			// The proof cannot be used twice
+			assert_noop!(
+				Crowdloan::associate_native_identity_with_proof(
+					Origin::none(),
+					10 + *i as u64,
+					relay_account,
+					reward,
+					signature,
+					proofs[*i].clone()
+				),
+				Error::<Test>::AlreadyAssociated
+			);
+		}
+	});
+}
+
+#[test]
+fn merkle_association_rejects_wrong_amounts_and_proofs() {
+	let pairs = get_ed25519_pairs(3);
+	let relay_accounts: Vec<[u8; 32]> = pairs.iter().map(|pair| pair.public().into()).collect();
+	let leaves = relay_accounts
+		.iter()
+		.map(|relay_account| crate::merkle::leaf(relay_account, &500u128))
+		.collect::<Vec<_>>();
+	let (root, proofs) = merkle_tree(leaves);
+	genesis_with_config(Default::default(), 1000).execute_with(|| {
+		let signature = association_signature(&pairs[0], 3);
+		// There is no root yet
+		assert_noop!(
+			Crowdloan::associate_native_identity_with_proof(
+				Origin::none(),
+				3,
+				relay_accounts[0],
+				500,
+				signature.clone(),
+				proofs[0].clone()
+			),
+			Error::<Test>::NoAssociatedClaim
+		);
+
+		// Only root can set it
+		assert_noop!(
+			Crowdloan::set_contributions_merkle_root(Origin::signed(3), root),
+			BadOrigin
+		);
+		assert_ok!(Crowdloan::set_contributions_merkle_root(
+			Origin::root(),
+			root
+		));
+
+		// Claiming more than the leaf says
+		assert_noop!(
+			Crowdloan::associate_native_identity_with_proof(
+				Origin::none(),
+				3,
+				relay_accounts[0],
+				501,
+				signature.clone(),
+				proofs[0].clone()
+			),
+			Error::<Test>::InvalidMerkleProof
+		);
+		// Using somebody else's proof
+		let call = crate::Call::<Test>::associate_native_identity_with_proof(
+			3,
+			relay_accounts[0],
+			500,
+			signature.clone(),
+			proofs[1].clone(),
+		);
+		assert_eq!(
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call),
+			Err(InvalidTransaction::BadProof.into())
+		);
+		// Proving somebody else's leaf without their signature
+		assert_noop!(
+			Crowdloan::associate_native_identity_with_proof(
+				Origin::none(),
+				3,
+				relay_accounts[1],
+				500,
+				signature,
+				proofs[1].clone()
+			),
+			Error::<Test>::InvalidClaimSignature
+		);
+
+		assert_eq!(
+			events(),
+			vec![crate::Event::ContributionsMerkleRootSet(root)]
+		);
+	});
+}
+
+#[test]
+fn initialize_reward_vec_in_chunks_works() {
+	let pairs = get_ed25519_pairs(2);
+	genesis_with_config(Default::default(), 2500).execute_with(|| {
+		assert!(!Crowdloan::initialized());
+		assert_ok!(Crowdloan::initialize_reward_vec(
+			Origin::root(),
+			vec![
+				([1u8; 32], Some(1), 500),
+				([2u8; 32], Some(2), 500),
+				(pairs[0].public().into(), None, 500),
+			]
+		));
+		assert_ok!(Crowdloan::initialize_reward_vec(
+			Origin::root(),
+			vec![(pairs[1].public().into(), None, 500)]
+		));
+		assert_eq!(Crowdloan::initialized_reward_amount(), 2000);
+		assert!(Crowdloan::accounts_payable(&1).is_some());
+		assert!(Crowdloan::claimed_relay_chain_ids(&[1u8; 32]).is_some());
+		assert!(Crowdloan::unassociated_contributions(pairs[0].public().as_array_ref()).is_some());
+
+		// No payouts until the set is locked
 		roll_to(4);
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(2)));
-		assert_ok!(Crowdloan::update_reward_address(Origin::signed(1), 2));
-		assert_eq!(Crowdloan::accounts_payable(&2).unwrap().last_paid, 4u64);
-		assert_eq!(Crowdloan::accounts_payable(&2).unwrap().claimed_reward, 496);
 		assert_noop!(
 			Crowdloan::show_me_the_money(Origin::signed(1)),
-			Error::<Test>::NoAssociatedClaim
+			Error::<Test>::RewardVecNotFullyInitializedYet
 		);
-		roll_to(6);
-		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(2)));
-		assert_eq!(Crowdloan::accounts_payable(&2).unwrap().last_paid, 6u64);
-		assert_eq!(Crowdloan::accounts_payable(&2).unwrap().claimed_reward, 746);
+		// Unassociated contributors can associate in the meantime
+		let signature = association_signature(&pairs[0], 3);
+		assert_ok!(Crowdloan::associate_native_identity(
+			Origin::signed(4),
+			3,
+			pairs[0].public().into(),
+			signature
+		));
+
+		// The total must match what the origin expects
+		assert_noop!(
+			Crowdloan::complete_initialization(Origin::root(), 2500),
+			Error::<Test>::InitializedRewardAmountMismatch
+		);
+		assert_ok!(Crowdloan::complete_initialization(Origin::root(), 2000));
+		assert!(Crowdloan::initialized());
+
+		// The set is locked
+		assert_noop!(
+			Crowdloan::initialize_reward_vec(Origin::root(), vec![([9u8; 32], Some(9), 500)]),
+			Error::<Test>::RewardVecAlreadyInitialized
+		);
+		assert_noop!(
+			Crowdloan::complete_initialization(Origin::root(), 2000),
+			Error::<Test>::RewardVecAlreadyInitialized
+		);
+
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+		assert_eq!(Balances::free_balance(&3), 250);
+
 		let expected = vec![
-			crate::Event::RewardsPaid(1, 248),
-			crate::Event::RewardsPaid(2, 248),
-			crate::Event::RewardAddressUpdated(1, 2),
-			crate::Event::RewardsPaid(2, 250),
+			crate::Event::RewardsInitialized(3, 1500),
+			crate::Event::RewardsInitialized(1, 2000),
+			crate::Event::NativeIdentityAssociated(pairs[0].public().into(), 3, 500),
+			crate::Event::InitializationCompleted(2000),
+			crate::Event::RewardsPaid(1, 250),
+			crate::Event::RewardsPaid(3, 250),
 		];
 		assert_eq!(events(), expected);
 	});
 }
+
+#[test]
+fn initialize_reward_vec_is_validated() {
+	genesis_with_config(Default::default(), 2500).execute_with(|| {
+		assert_noop!(
+			Crowdloan::initialize_reward_vec(Origin::signed(1), vec![([1u8; 32], Some(1), 500)]),
+			BadOrigin
+		);
+		assert_noop!(
+			Crowdloan::complete_initialization(Origin::signed(1), 0),
+			BadOrigin
+		);
+		// Chunks are bounded
+		assert_noop!(
+			Crowdloan::initialize_reward_vec(
+				Origin::root(),
+				(1u8..=5).map(|i| ([i; 32], None, 500)).collect()
+			),
+			Error::<Test>::TooManyContributors
+		);
+		// Duplicates within a chunk
+		assert_noop!(
+			Crowdloan::initialize_reward_vec(
+				Origin::root(),
+				vec![([1u8; 32], Some(1), 500), ([1u8; 32], None, 500)]
+			),
+			Error::<Test>::RelayAccountAlreadyRegistered
+		);
+		// And across chunks
+		assert_ok!(Crowdloan::initialize_reward_vec(
+			Origin::root(),
+			vec![([1u8; 32], None, 500)]
+		));
+		assert_noop!(
+			Crowdloan::initialize_reward_vec(Origin::root(), vec![([1u8; 32], Some(1), 500)]),
+			Error::<Test>::RelayAccountAlreadyRegistered
+		);
+	});
+}
+
+#[test]
+fn genesis_contributions_complete_initialization() {
+	two_assigned_three_unassigned().execute_with(|| {
+		assert!(Crowdloan::initialized());
+		assert_eq!(Crowdloan::initialized_reward_amount(), 2500);
+		assert_noop!(
+			Crowdloan::initialize_reward_vec(Origin::root(), vec![([9u8; 32], Some(9), 500)]),
+			Error::<Test>::RewardVecAlreadyInitialized
+		);
+	});
+}
+
+#[test]
+fn genesis_starts_at_the_latest_storage_version() {
+	two_assigned_three_unassigned().execute_with(|| {
+		assert_eq!(StorageVersion::<Test>::get(), Releases::LATEST);
+		// Nothing to migrate
+		Crowdloan::on_runtime_upgrade();
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().total_reward, 500);
+	});
+}
+
+#[test]
+fn migration_to_v2_translates_legacy_reward_info() {
+	genesis_with_config(Default::default(), 1).execute_with(|| {
+		// Storage as written by the original layout, before versioning
+		StorageVersion::<Test>::kill();
+		let legacy =
+			|total: u128, claimed: u128, last_paid: u64| (total, claimed, last_paid).encode();
+		unhashed::put_raw(
+			&AccountsPayable::<Test>::hashed_key_for(&1),
+			&legacy(500, 124, 2),
+		);
+		unhashed::put_raw(
+			&UnassociatedContributions::<Test>::hashed_key_for(&[3u8; 32]),
+			&legacy(700, 0, 0),
+		);
+
+		Crowdloan::on_runtime_upgrade();
+
+		// Then on to the latest layout
+		assert_eq!(StorageVersion::<Test>::get(), Releases::LATEST);
+		let info = Crowdloan::accounts_payable(&1).unwrap();
+		assert_eq!(
+			(
+				info.total_reward,
+				info.claimed_reward,
+				info.last_paid,
+				info.initial_payment
+			),
+			(500, 124, 2, 0)
+		);
+		let info = Crowdloan::unassociated_contributions(&[3u8; 32]).unwrap();
+		assert_eq!((info.total_reward, info.claimed_reward), (700, 0));
+		assert!(Crowdloan::initialized());
+		assert_eq!(Crowdloan::initialized_reward_amount(), 1200);
+
+		// Running the upgrade again does not touch the migrated entries
+		Crowdloan::on_runtime_upgrade();
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 124);
+	});
+}
+
+#[test]
+fn migration_to_v3_keeps_legacy_associations() {
+	genesis_with_config(Default::default(), 1).execute_with(|| {
+		StorageVersion::<Test>::put(Releases::V2_0_0);
+		let relay_account = [9u8; 32];
+		unhashed::put_raw(
+			&ClaimedRelayChainIds::<Test>::hashed_key_for(&relay_account),
+			&().encode(),
+		);
+		let address = ethereum_address(&ethereum_secret(b"contributor"));
+		unhashed::put_raw(
+			&ClaimedEthereumAddresses::<Test>::hashed_key_for(&address),
+			&().encode(),
+		);
+
+		Crowdloan::on_runtime_upgrade();
+
+		assert_eq!(StorageVersion::<Test>::get(), Releases::V3_0_0);
+		// Still associated, to an unknown account
+		assert_eq!(
+			Crowdloan::claimed_relay_chain_ids(&relay_account),
+			Some(None)
+		);
+		assert!(Crowdloan::associated_relay_accounts(&0).is_empty());
+		assert_eq!(Crowdloan::claimed_ethereum_addresses(&address), Some(None));
+		assert!(Crowdloan::associated_ethereum_addresses(&0).is_empty());
+	});
+}
+
+#[test]
+fn paying_works() {
+	two_assigned_three_unassigned().execute_with(|| {
+		// 1 is payable
+		assert!(Crowdloan::accounts_payable(&1).is_some());
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().last_paid, 4u64);
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 250);
+		assert_noop!(
+			Crowdloan::show_me_the_money(Origin::signed(3)),
+			Error::<Test>::NoAssociatedClaim
+		);
+		roll_to(5);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().last_paid, 5u64);
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 312);
+		roll_to(6);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().last_paid, 6u64);
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 375);
+		roll_to(7);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().last_paid, 7u64);
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 437);
+		roll_to(230);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 500);
+		roll_to(330);
+		assert_noop!(
+			Crowdloan::show_me_the_money(Origin::signed(1)),
+			Error::<Test>::RewardsAlreadyClaimed
+		);
+
+		let expected = vec![
+			crate::Event::RewardsPaid(1, 250),
+			crate::Event::RewardsPaid(1, 62),
+			crate::Event::RewardsPaid(1, 63),
+			crate::Event::RewardsPaid(1, 62),
+			crate::Event::RewardsPaid(1, 63),
+		];
+		assert_eq!(events(), expected);
+	});
+}
+
+#[test]
+fn claim_for_pays_the_beneficiary() {
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(4);
+		// Anyone can trigger the payout, but only the beneficiary gets paid
+		assert_ok!(Crowdloan::claim_for(Origin::signed(7), 1));
+		assert_eq!(Balances::free_balance(&1), 250);
+		assert_eq!(Balances::free_balance(&7), 0);
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 250);
+
+		// It shares the checks of show_me_the_money
+		assert_noop!(
+			Crowdloan::claim_for(Origin::signed(7), 3),
+			Error::<Test>::NoAssociatedClaim
+		);
+		assert_noop!(Crowdloan::claim_for(Origin::none(), 1), BadOrigin);
+
+		// And the accounting, so the beneficiary can keep claiming on its own
+		roll_to(6);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Balances::free_balance(&1), 375);
+		roll_to(8);
+		assert_ok!(Crowdloan::claim_for(Origin::signed(7), 1));
+		assert_noop!(
+			Crowdloan::claim_for(Origin::signed(7), 1),
+			Error::<Test>::RewardsAlreadyClaimed
+		);
+
+		let expected = vec![
+			crate::Event::RewardsPaid(1, 250),
+			crate::Event::RewardsPaid(1, 125),
+			crate::Event::RewardsPaid(1, 125),
+		];
+		assert_eq!(events(), expected);
+	});
+}
+
+#[test]
+fn payout_batch_skips_accounts_that_cannot_be_paid() {
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(2)));
+		roll_to(8);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(2)));
+		// 3 has no rewards, and 2 has claimed everything already
+		assert_ok!(Crowdloan::payout_batch(Origin::signed(7), vec![1, 3, 2]));
+		assert_eq!(Balances::free_balance(&1), 500);
+		assert_eq!(Balances::free_balance(&2), 500);
+
+		let expected = vec![
+			crate::Event::RewardsPaid(2, 250),
+			crate::Event::RewardsPaid(2, 250),
+			crate::Event::RewardsPaid(1, 500),
+			crate::Event::PayoutSkipped(3, Error::<Test>::NoAssociatedClaim.into()),
+			crate::Event::PayoutSkipped(2, Error::<Test>::RewardsAlreadyClaimed.into()),
+		];
+		assert_eq!(events(), expected);
+	});
+}
+
+#[test]
+fn payout_batch_is_bounded() {
+	two_assigned_three_unassigned().execute_with(|| {
+		assert_noop!(
+			Crowdloan::payout_batch(Origin::signed(7), vec![1, 2, 1, 2, 1]),
+			Error::<Test>::TooManyBeneficiaries
+		);
+		assert_noop!(Crowdloan::payout_batch(Origin::none(), vec![1]), BadOrigin);
+	});
+	genesis_with_config(Default::default(), 1).execute_with(|| {
+		assert_noop!(
+			Crowdloan::payout_batch(Origin::signed(7), vec![1]),
+			Error::<Test>::RewardVecNotFullyInitializedYet
+		);
+	});
+}
+
+#[test]
+fn lock_mode_pays_the_whole_reward_and_locks_the_rest() {
+	set_payout_mode(PayoutMode::Lock);
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Balances::free_balance(&1), 500);
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 500);
+		// Half is vested at block 4, the other half unlocks over the 4 remaining blocks
+		assert_eq!(Vesting::vesting_balance(&1), Some(250));
+		assert_noop!(
+			Balances::transfer(Origin::signed(1), 7, 251),
+			pallet_balances::Error::<Test>::LiquidityRestrictions
+		);
+		roll_to(6);
+		assert_eq!(Vesting::vesting_balance(&1), Some(124));
+		roll_to(8);
+		assert_eq!(Vesting::vesting_balance(&1), Some(0));
+
+		// There is nothing left to claim
+		assert_noop!(
+			Crowdloan::show_me_the_money(Origin::signed(1)),
+			Error::<Test>::RewardsAlreadyClaimed
+		);
+		assert_eq!(events(), vec![crate::Event::RewardsPaid(1, 500)]);
+	});
+}
+
+#[test]
+fn lock_mode_needs_a_free_vesting_schedule() {
+	set_payout_mode(PayoutMode::Lock);
+	two_assigned_three_unassigned().execute_with(|| {
+		Balances::make_free_balance_be(&1, 10);
+		assert_ok!(Vesting::add_vesting_schedule(&1, 10, 1, 1));
+		roll_to(4);
+		assert_noop!(
+			Crowdloan::show_me_the_money(Origin::signed(1)),
+			Error::<Test>::ExistingVestingSchedule
+		);
+
+		// Nothing needs locking once the reward is fully vested
+		roll_to(8);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Balances::free_balance(&1), 510);
+		assert_eq!(Vesting::vesting_balance(&1), Some(3));
+	});
+}
+
+#[test]
+fn lock_mode_locks_later_contributions_with_the_first_ones() {
+	set_payout_mode(PayoutMode::Lock);
+	let pairs = get_ed25519_pairs(2);
+	two_assigned_three_unassigned().execute_with(|| {
+		let associate = |pair: &sp_core::ed25519::Pair| {
+			assert_ok!(Crowdloan::associate_native_identity_unsigned(
+				Origin::none(),
+				3,
+				pair.public().into(),
+				association_signature(pair, 3)
+			));
+		};
+		associate(&pairs[0]);
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+		assert_eq!(Vesting::vesting_balance(&3), Some(250));
+
+		// Only the unvested quarter of the new contribution is locked on top of what is left of
+		// the first lock
+		associate(&pairs[1]);
+		roll_to(6);
+		assert_eq!(Vesting::vesting_balance(&3), Some(124));
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+		assert_eq!(Balances::free_balance(&3), 1000);
+		assert_eq!(
+			Crowdloan::accounts_payable(&3).unwrap().claimed_reward,
+			1000
+		);
+		assert_eq!(Vesting::vesting_balance(&3), Some(249));
+		assert_eq!(Crowdloan::reward_vesting_schedules(&3), Some((249, 125, 6)));
+		assert_noop!(
+			Balances::transfer(Origin::signed(3), 7, 752),
+			pallet_balances::Error::<Test>::LiquidityRestrictions
+		);
+
+		// Everything unlocks at the end of the vesting period
+		roll_to(8);
+		assert_eq!(Vesting::vesting_balance(&3), Some(0));
+		assert_noop!(
+			Crowdloan::show_me_the_money(Origin::signed(3)),
+			Error::<Test>::RewardsAlreadyClaimed
+		);
+		assert_eq!(paid_accounts(), vec![(3, 500), (3, 500)]);
+	});
+}
+
+#[test]
+fn lock_mode_never_touches_schedules_it_did_not_create() {
+	set_payout_mode(PayoutMode::Lock);
+	let pairs = get_ed25519_pairs(2);
+	two_assigned_three_unassigned().execute_with(|| {
+		let associate = |pair: &sp_core::ed25519::Pair, account: AccountId| {
+			assert_ok!(Crowdloan::associate_native_identity_unsigned(
+				Origin::none(),
+				account,
+				pair.public().into(),
+				association_signature(pair, account)
+			));
+		};
+		associate(&pairs[0], 3);
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+
+		// Account 4 has a vesting schedule of its own when it is given the paid out reward and a
+		// new contribution
+		Balances::make_free_balance_be(&4, 100);
+		assert_ok!(Vesting::add_vesting_schedule(&4, 100, 10, 0));
+		assert_ok!(Crowdloan::update_reward_address(Origin::signed(3), 4));
+		associate(&pairs[1], 4);
+		roll_to(6);
+		assert_noop!(
+			Crowdloan::show_me_the_money(Origin::signed(4)),
+			Error::<Test>::ExistingVestingSchedule
+		);
+		assert_eq!(Vesting::vesting_balance(&4), Some(40));
+		// The lock of the first payout stays with the funds it locks
+		assert_eq!(Vesting::vesting_balance(&3), Some(124));
+
+		// Once the reward is fully vested it is paid without any lock
+		roll_to(8);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(4)));
+		assert_eq!(Balances::free_balance(&4), 600);
+		assert_eq!(Vesting::vesting_balance(&4), Some(20));
+		assert_eq!(Crowdloan::reward_vesting_schedules(&4), None);
+	});
+}
+
+#[test]
+fn lock_mode_auto_payouts_pay_rewards_that_are_not_vested_yet() {
+	set_payout_mode(PayoutMode::Lock);
+	set_auto_payouts(1, Weight::max_value());
+	lease_vesting_genesis().execute_with(|| {
+		// Nothing vests before block 10, yet the whole reward can be paid and locked
+		assert_eq!(Crowdloan::vested_for(&1), 0);
+		assert_eq!(Crowdloan::claimable_for(&1), 500);
+		roll_to(2);
+		assert_eq!(paid_accounts(), vec![(1, 500)]);
+		assert_eq!(Vesting::vesting_balance(&1), Some(500));
+		assert_eq!(Crowdloan::claimable_for(&1), 0);
+	});
+}
+
+#[test]
+fn lock_mode_auto_claims_rewards_that_are_not_vested_yet() {
+	set_payout_mode(PayoutMode::Lock);
+	UintAuthorityId::set_all_keys(vec![7u64]);
+	let (pool, pool_state) = TestTransactionPoolExt::new();
+	let mut ext = lease_vesting_genesis();
+	ext.register_extension(TransactionPoolExt::new(pool));
+	ext.execute_with(|| {
+		assert_ok!(Crowdloan::set_auto_claim_authority(Origin::root(), Some(7)));
+		roll_to(2);
+		Crowdloan::offchain_worker(2);
+		let transaction = pool_state.write().transactions.pop().unwrap();
+		let (payload, signature) = decode_auto_claim(transaction);
+		assert_eq!(payload.beneficiaries, vec![1]);
+		assert_ok!(Crowdloan::auto_claim(Origin::none(), payload, signature));
+		assert_eq!(Balances::free_balance(&1), 500);
+		assert_eq!(Vesting::vesting_balance(&1), Some(500));
+	});
+}
+
+#[test]
+fn auto_claims_move_past_accounts_that_cannot_be_paid() {
+	set_payout_mode(PayoutMode::Lock);
+	UintAuthorityId::set_all_keys(vec![7u64]);
+	let (pool, pool_state) = TestTransactionPoolExt::new();
+	let mut ext = genesis(
+		(1..=6u8).map(|i| ([i; 32], i as AccountId, 500)).collect(),
+		vec![],
+		FixedU128::saturating_from_integer(1u128),
+		3000,
+	);
+	ext.register_extension(TransactionPoolExt::new(pool));
+	ext.execute_with(|| {
+		// Only account 6 can be paid, the others have vesting schedules of their own
+		for account in 1..=5 {
+			Balances::make_free_balance_be(&account, 100);
+			assert_ok!(Vesting::add_vesting_schedule(&account, 100, 1, 0));
+		}
+		assert_ok!(Crowdloan::set_auto_claim_authority(Origin::root(), Some(7)));
+
+		let mut beneficiaries = Vec::new();
+		for block in 2..=3 {
+			roll_to(block);
+			Crowdloan::offchain_worker(block);
+			let transaction = pool_state.write().transactions.pop().unwrap();
+			let (payload, signature) = decode_auto_claim(transaction);
+			assert_eq!(payload.beneficiaries.len(), 4);
+			beneficiaries.extend(payload.beneficiaries.clone());
+			assert_ok!(Crowdloan::auto_claim(Origin::none(), payload, signature));
+		}
+		// The second batch starts with the accounts the first one did not reach
+		beneficiaries.sort();
+		beneficiaries.dedup();
+		assert_eq!(beneficiaries, vec![1, 2, 3, 4, 5, 6]);
+		assert_eq!(Balances::free_balance(&6), 500);
+	});
+}
+
+fn paid_accounts() -> Vec<(AccountId, Balance)> {
+	events()
+		.into_iter()
+		.filter_map(|event| match event {
+			crate::Event::RewardsPaid(account, amount) => Some((account, amount)),
+			_ => None,
+		})
+		.collect()
+}
+
+#[test]
+fn auto_payouts_walk_accounts_payable() {
+	set_auto_payouts(1, Weight::max_value());
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(2);
+		roll_to(3);
+		// One account per block, each getting what is vested by then
+		let paid = paid_accounts();
+		assert_eq!(paid.len(), 2);
+		assert_ne!(paid[0].0, paid[1].0);
+		assert_eq!((paid[0].1, paid[1].1), (125, 187));
+
+		// The end of the map was reached, so the walk starts over
+		roll_to(4);
+		assert_eq!(paid_accounts().len(), 2);
+		roll_to(5);
+		assert_eq!(paid_accounts()[2], (paid[0].0, 187));
+
+		roll_to(12);
+		assert_eq!(Balances::free_balance(&1), 500);
+		assert_eq!(Balances::free_balance(&2), 500);
+	});
+}
+
+#[test]
+fn auto_payouts_respect_the_weight_budget() {
+	set_auto_payouts(2, <() as WeightInfo>::claim_for());
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(2);
+		assert_eq!(paid_accounts().len(), 1);
+		roll_to(3);
+		assert_eq!(paid_accounts().len(), 2);
+	});
+}
+
+#[test]
+fn auto_payouts_wait_for_initialization() {
+	set_auto_payouts(4, Weight::max_value());
+	genesis_with_config(Default::default(), 1000).execute_with(|| {
+		assert_ok!(Crowdloan::initialize_reward_vec(
+			Origin::root(),
+			vec![([1u8; 32], Some(1), 500)]
+		));
+		roll_to(4);
+		assert!(paid_accounts().is_empty());
+		assert_ok!(Crowdloan::complete_initialization(Origin::root(), 500));
+		roll_to(5);
+		assert_eq!(paid_accounts(), vec![(1, 312)]);
+	});
+}
+
+type TestAutoClaimPayload = AutoClaimPayload<UintAuthorityId, AccountId, u64>;
+
+fn decode_auto_claim(transaction: Vec<u8>) -> (TestAutoClaimPayload, TestSignature) {
+	let transaction = Extrinsic::decode(&mut &*transaction).unwrap();
+	assert_eq!(transaction.signature, None);
+	match transaction.call {
+		mock::Call::Crowdloan(crate::Call::auto_claim(payload, signature)) => (payload, signature),
+		call => panic!("Unexpected call: {:?}", call),
+	}
+}
+
+#[test]
+fn offchain_worker_submits_auto_claims() {
+	UintAuthorityId::set_all_keys(vec![7u64]);
+	let (pool, pool_state) = TestTransactionPoolExt::new();
+	let mut ext = two_assigned_three_unassigned();
+	ext.register_extension(TransactionPoolExt::new(pool));
+	ext.execute_with(|| {
+		// Nothing is submitted until there is an authority
+		roll_to(2);
+		Crowdloan::offchain_worker(2);
+		assert!(pool_state.read().transactions.is_empty());
+		assert_ok!(Crowdloan::set_auto_claim_authority(Origin::root(), Some(7)));
+
+		// Only 62 are claimable at block 1, which is below the threshold
+		Crowdloan::offchain_worker(1);
+		assert!(pool_state.read().transactions.is_empty());
+
+		Crowdloan::offchain_worker(2);
+		let transaction = pool_state.write().transactions.pop().unwrap();
+		assert!(pool_state.read().transactions.is_empty());
+		let (payload, signature) = decode_auto_claim(transaction);
+		let mut beneficiaries = payload.beneficiaries.clone();
+		beneficiaries.sort();
+		assert_eq!(beneficiaries, vec![1, 2]);
+		assert_eq!(payload.block_number, 2);
+		assert_eq!(payload.public, UintAuthorityId(7));
+
+		let call = crate::Call::<Test>::auto_claim(payload.clone(), signature.clone());
+		let valid =
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::Local, &call)
+				.unwrap();
+		assert_eq!(
+			valid.provides,
+			vec![("CrowdloanRewardsAutoClaim", 2u64).encode()]
+		);
+		assert_ok!(Crowdloan::auto_claim(Origin::none(), payload, signature));
+		assert_eq!(Balances::free_balance(&1), 125);
+		assert_eq!(Balances::free_balance(&2), 125);
+	});
+}
+
+#[test]
+fn auto_claims_must_be_signed_by_the_authority() {
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(2);
+		let validate = |payload: TestAutoClaimPayload, signature: TestSignature| {
+			let call = crate::Call::<Test>::auto_claim(payload, signature);
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call)
+		};
+		let payload = |public: u64, block_number: u64| AutoClaimPayload {
+			beneficiaries: vec![1, 2],
+			block_number,
+			public: UintAuthorityId(public),
+		};
+		let sign =
+			|payload: &TestAutoClaimPayload| TestSignature(payload.public.0, payload.encode());
+
+		// Automatic claims are disabled
+		assert_eq!(
+			validate(payload(7, 2), sign(&payload(7, 2))),
+			Err(InvalidTransaction::Call.into())
+		);
+
+		assert_ok!(Crowdloan::set_auto_claim_authority(Origin::root(), Some(7)));
+		assert_ok!(validate(payload(7, 2), sign(&payload(7, 2))));
+		// Signed by someone else
+		assert_eq!(
+			validate(payload(8, 2), sign(&payload(8, 2))),
+			Err(InvalidTransaction::BadSigner.into())
+		);
+		// Tampered with
+		let mut tampered = payload(7, 2);
+		tampered.beneficiaries.push(3);
+		assert_eq!(
+			validate(tampered, sign(&payload(7, 2))),
+			Err(InvalidTransaction::BadProof.into())
+		);
+		// From the future
+		assert_eq!(
+			validate(payload(7, 3), sign(&payload(7, 3))),
+			Err(InvalidTransaction::Future.into())
+		);
+		// Too many beneficiaries
+		let mut oversized = payload(7, 2);
+		oversized.beneficiaries = vec![1, 2, 3, 4, 5];
+		assert_eq!(
+			validate(oversized.clone(), sign(&oversized)),
+			Err(InvalidTransaction::ExhaustsResources.into())
+		);
+
+		assert_noop!(
+			Crowdloan::auto_claim(Origin::signed(7), payload(7, 2), sign(&payload(7, 2))),
+			BadOrigin
+		);
+		assert_noop!(
+			Crowdloan::set_auto_claim_authority(Origin::signed(7), None),
+			BadOrigin
+		);
+		assert_ok!(Crowdloan::set_auto_claim_authority(Origin::root(), None));
+		assert_eq!(Crowdloan::auto_claim_authority(), None);
+	});
+}
+
+#[test]
+fn auto_claims_cannot_be_replayed() {
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(2);
+		assert_ok!(Crowdloan::set_auto_claim_authority(Origin::root(), Some(7)));
+		let validate = |payload: TestAutoClaimPayload, signature: TestSignature| {
+			let call = crate::Call::<Test>::auto_claim(payload, signature);
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call)
+		};
+		let payload = |block_number: u64| AutoClaimPayload {
+			beneficiaries: vec![1, 2],
+			block_number,
+			public: UintAuthorityId(7),
+		};
+		let sign =
+			|payload: &TestAutoClaimPayload| TestSignature(payload.public.0, payload.encode());
+
+		assert_ok!(validate(payload(1), sign(&payload(1))));
+		assert_ok!(Crowdloan::auto_claim(
+			Origin::none(),
+			payload(2),
+			sign(&payload(2))
+		));
+		assert_eq!(Crowdloan::last_auto_claim_block(), Some(2));
+
+		// The same payload, or an older one, is rejected
+		assert_eq!(
+			validate(payload(2), sign(&payload(2))),
+			Err(InvalidTransaction::Stale.into())
+		);
+		assert_eq!(
+			validate(payload(1), sign(&payload(1))),
+			Err(InvalidTransaction::Stale.into())
+		);
+		assert_noop!(
+			Crowdloan::auto_claim(Origin::none(), payload(2), sign(&payload(2))),
+			Error::<Test>::StaleAutoClaim
+		);
+
+		// A newer payload goes through, unless it has outlived its longevity
+		roll_to(3);
+		assert_ok!(validate(payload(3), sign(&payload(3))));
+		roll_to(9);
+		assert_eq!(
+			validate(payload(3), sign(&payload(3))),
+			Err(InvalidTransaction::Stale.into())
+		);
+		assert_noop!(
+			Crowdloan::auto_claim(Origin::none(), payload(3), sign(&payload(3))),
+			Error::<Test>::StaleAutoClaim
+		);
+		assert_ok!(Crowdloan::auto_claim(
+			Origin::none(),
+			payload(9),
+			sign(&payload(9))
+		));
+	});
+}
+
+#[test]
+fn claimable_matches_what_is_paid() {
+	two_assigned_three_unassigned().execute_with(|| {
+		assert_eq!(Crowdloan::claimable_for(&3), 0);
+		assert_eq!(Crowdloan::vested_for(&3), 0);
+		for block in [4u64, 5, 7, 8, 12].iter() {
+			roll_to(*block);
+			let claimable = Crowdloan::claimable_for(&1);
+			let claimed_before = Crowdloan::accounts_payable(&1).unwrap().claimed_reward;
+			assert_eq!(Crowdloan::vested_for(&1), claimed_before + claimable);
+			if claimable > 0 {
+				assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+			}
+			assert_eq!(
+				Crowdloan::accounts_payable(&1).unwrap().claimed_reward,
+				claimed_before + claimable
+			);
+			assert_eq!(Crowdloan::claimable_for(&1), 0);
+		}
+		assert_eq!(Crowdloan::vested_for(&1), 500);
+	});
+}
+
+#[test]
+fn nothing_is_claimable_before_initialization() {
+	genesis_with_config(Default::default(), 1).execute_with(|| {
+		assert_ok!(Crowdloan::initialize_reward_vec(
+			Origin::root(),
+			vec![([1u8; 32], Some(1), 500)]
+		));
+		roll_to(4);
+		assert_eq!(Crowdloan::claimable_for(&1), 0);
+		assert_eq!(Crowdloan::vested_for(&1), 250);
+	});
+}
+
+#[test]
+fn paying_late_joiner_works() {
+	let pairs = get_ed25519_pairs(3);
+	two_assigned_three_unassigned().execute_with(|| {
+		let signature = association_signature(&pairs[0], 3);
+		//
+		roll_to(12);
+		assert_ok!(Crowdloan::associate_native_identity(
+			Origin::signed(4),
+			3,
+			pairs[0].public().into(),
+			signature.clone()
+		));
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+		assert_eq!(Crowdloan::accounts_payable(&3).unwrap().last_paid, 12u64);
+		assert_eq!(Crowdloan::accounts_payable(&3).unwrap().claimed_reward, 500);
+		let expected = vec![
+			crate::Event::NativeIdentityAssociated(pairs[0].public().into(), 3, 500),
+			crate::Event::RewardsPaid(3, 500),
+		];
+		assert_eq!(events(), expected);
+	});
+}
+
+fn lease_vesting_genesis() -> sp_io::TestExternalities {
+	let pairs = get_ed25519_pairs(1);
+	genesis_with_config(
+		GenesisConfig::<Test> {
+			associated: vec![([1u8; 32], 1, 500.into())],
+			unassociated: vec![(pairs[0].public().into(), 500.into())],
+			vesting_start_block: 10,
+			vesting_end_block: Some(18),
+			..Default::default()
+		},
+		1000,
+	)
+}
+
+#[test]
+fn vesting_is_relative_to_the_start_block() {
+	lease_vesting_genesis().execute_with(|| {
+		assert_eq!(Crowdloan::vesting_schedule(), (10, 18));
+		roll_to(6);
+		assert_eq!(Crowdloan::claimable_for(&1), 0);
+		roll_to(14);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 250);
+		roll_to(18);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 500);
+	});
+}
+
+#[test]
+fn late_joiner_vests_from_the_start_block() {
+	let pairs = get_ed25519_pairs(1);
+	lease_vesting_genesis().execute_with(|| {
+		roll_to(12);
+		assert_ok!(Crowdloan::associate_native_identity(
+			Origin::signed(4),
+			3,
+			pairs[0].public().into(),
+			association_signature(&pairs[0], 3)
+		));
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+		// Two of the eight vesting blocks have elapsed
+		assert_eq!(Crowdloan::accounts_payable(&3).unwrap().claimed_reward, 125);
+	});
+}
+
+#[test]
+fn set_vesting_schedule_works() {
+	genesis_with_config(Default::default(), 1).execute_with(|| {
+		assert_noop!(
+			Crowdloan::set_vesting_schedule(Origin::signed(1), 10, 18),
+			BadOrigin
+		);
+		assert_noop!(
+			Crowdloan::set_vesting_schedule(Origin::root(), 10, 10),
+			Error::<Test>::InvalidVestingSchedule
+		);
+		assert_noop!(
+			Crowdloan::set_vesting_schedule(Origin::root(), 0, 10),
+			Error::<Test>::InvalidVestingSchedule
+		);
+		// Vesting has not started while contributions are being initialized
+		assert_ok!(Crowdloan::set_vesting_schedule(Origin::root(), 10, 18));
+		assert_eq!(Crowdloan::vesting_schedule(), (10, 18));
+		assert_ok!(Crowdloan::complete_initialization(Origin::root(), 0));
+
+		// Can still be pushed back until it starts
+		assert_ok!(Crowdloan::set_vesting_schedule(Origin::root(), 12, 20));
+		roll_to(12);
+		assert_noop!(
+			Crowdloan::set_vesting_schedule(Origin::root(), 14, 20),
+			Error::<Test>::VestingAlreadyStarted
+		);
+		assert_eq!(
+			events(),
+			vec![
+				crate::Event::VestingScheduleSet(10, 18),
+				crate::Event::InitializationCompleted(0),
+				crate::Event::VestingScheduleSet(12, 20),
+			]
+		);
+	});
+}
+
+#[test]
+fn vesting_schedule_cannot_change_once_started_at_genesis() {
+	two_assigned_three_unassigned().execute_with(|| {
+		assert_eq!(Crowdloan::vesting_schedule(), (0, 8));
+		assert_noop!(
+			Crowdloan::set_vesting_schedule(Origin::root(), 10, 18),
+			Error::<Test>::VestingAlreadyStarted
+		);
+	});
+}
+
+#[test]
+fn update_address_works() {
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_noop!(
+			Crowdloan::show_me_the_money(Origin::signed(8)),
+			Error::<Test>::NoAssociatedClaim
+		);
+		assert_ok!(Crowdloan::update_reward_address(Origin::signed(1), 8));
+		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().last_paid, 4u64);
+		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().claimed_reward, 250);
+		roll_to(6);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(8)));
+		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().last_paid, 6u64);
+		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().claimed_reward, 375);
+		let expected = vec![
+			crate::Event::RewardsPaid(1, 250),
+			crate::Event::RewardAddressUpdated(1, 8),
+			crate::Event::RewardsPaid(8, 125),
+		];
+		assert_eq!(events(), expected);
+	});
+}
+
+#[test]
+fn update_address_with_existing_address_works() {
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(2)));
+		assert_ok!(Crowdloan::update_reward_address(Origin::signed(1), 2));
+		assert_eq!(Crowdloan::accounts_payable(&2).unwrap().last_paid, 4u64);
+		assert_eq!(Crowdloan::accounts_payable(&2).unwrap().claimed_reward, 500);
+		assert_noop!(
+			Crowdloan::show_me_the_money(Origin::signed(1)),
+			Error::<Test>::NoAssociatedClaim
+		);
+		roll_to(6);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(2)));
+		assert_eq!(Crowdloan::accounts_payable(&2).unwrap().last_paid, 6u64);
+		assert_eq!(Crowdloan::accounts_payable(&2).unwrap().claimed_reward, 750);
+		let expected = vec![
+			crate::Event::RewardsPaid(1, 250),
+			crate::Event::RewardsPaid(2, 250),
+			crate::Event::RewardAddressUpdated(1, 2),
+			crate::Event::RewardsPaid(2, 250),
+		];
+		assert_eq!(events(), expected);
+	});
+}
+
+#[test]
+fn contributions_from_several_relay_accounts_add_up() {
+	let pairs = get_ed25519_pairs(3);
+	two_assigned_three_unassigned().execute_with(|| {
+		let associate = |pair: &sp_core::ed25519::Pair| {
+			assert_ok!(Crowdloan::associate_native_identity_unsigned(
+				Origin::none(),
+				3,
+				pair.public().into(),
+				association_signature(pair, 3)
+			));
+		};
+		associate(&pairs[0]);
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+		assert_eq!(Balances::free_balance(&3), 250);
+
+		// The second contribution is added, vesting as if it had been associated all along
+		associate(&pairs[1]);
+		let info = Crowdloan::accounts_payable(&3).unwrap();
+		assert_eq!((info.total_reward, info.claimed_reward), (1000, 250));
+		assert_eq!(Crowdloan::claimable_for(&3), 250);
+		roll_to(6);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+		assert_eq!(Balances::free_balance(&3), 750);
+
+		// And so is one that was associated to the account at genesis
+		associate(&pairs[2]);
+		assert_ok!(Crowdloan::update_reward_address(Origin::signed(1), 3));
+		roll_to(8);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+		assert_eq!(Balances::free_balance(&3), 2000);
+		assert_eq!(Crowdloan::associated_relay_accounts(&3).len(), 4);
+	});
+}
+
+#[test]
+fn relay_accounts_per_account_are_bounded() {
+	let pairs = get_ed25519_pairs(3);
+	two_assigned_three_unassigned().execute_with(|| {
+		let associate = |pair: &sp_core::ed25519::Pair| {
+			Crowdloan::associate_native_identity_unsigned(
+				Origin::none(),
+				3,
+				pair.public().into(),
+				association_signature(pair, 3),
+			)
+		};
+		assert_ok!(Crowdloan::update_reward_address(Origin::signed(1), 3));
+		assert_ok!(associate(&pairs[0]));
+		assert_ok!(associate(&pairs[1]));
+		assert_ok!(associate(&pairs[2]));
+		assert_eq!(Crowdloan::associated_relay_accounts(&3).len(), 4);
+
+		// Neither a new association nor a merge can go over the bound
+		assert_noop!(
+			Crowdloan::update_reward_address(Origin::signed(2), 3),
+			Error::<Test>::TooManyRelayAccounts
+		);
+		assert_ok!(Crowdloan::update_reward_address(Origin::signed(3), 4));
+		assert_noop!(
+			Crowdloan::update_reward_address(Origin::signed(2), 4),
+			Error::<Test>::TooManyRelayAccounts
+		);
+		assert_eq!(Crowdloan::associated_relay_accounts(&4).len(), 4);
+	});
+
+	let relay_account: [u8; 32] = pairs[0].public().into();
+	genesis(
+		(1u8..=4).map(|i| ([i; 32], 1, 500)).collect(),
+		vec![(relay_account, 500)],
+		FixedU128::saturating_from_integer(1u128),
+		2500,
+	)
+	.execute_with(|| {
+		let proof = association_signature(&pairs[0], 1);
+		let call = crate::Call::<Test>::associate_native_identity_unsigned(
+			1,
+			relay_account,
+			proof.clone(),
+		);
+		assert_eq!(
+			<Crowdloan as ValidateUnsigned>::validate_unsigned(TransactionSource::External, &call),
+			Err(InvalidTransaction::ExhaustsResources.into())
+		);
+		assert_noop!(
+			Crowdloan::associate_native_identity_unsigned(Origin::none(), 1, relay_account, proof),
+			Error::<Test>::TooManyRelayAccounts
+		);
+	});
+}
+
+#[test]
+#[should_panic(expected = "Too many relay chain accounts")]
+fn genesis_bounds_relay_accounts_per_account() {
+	genesis(
+		(1u8..=5).map(|i| ([i; 32], 1, 500)).collect(),
+		vec![],
+		FixedU128::saturating_from_integer(1u128),
+		2500,
+	);
+}
+
+#[test]
+fn registered_contributions_to_the_same_account_add_up() {
+	genesis_with_config(
+		GenesisConfig::<Test> {
+			associated: vec![([1u8; 32], 1, 500.into()), ([2u8; 32], 1, 300.into())],
+			..Default::default()
+		},
+		2000,
+	)
+	.execute_with(|| {
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().total_reward, 800);
+		assert_eq!(
+			Crowdloan::associated_relay_accounts(&1),
+			vec![[1u8; 32], [2u8; 32]]
+		);
+	});
+	genesis_with_config(Default::default(), 2000).execute_with(|| {
+		assert_ok!(Crowdloan::initialize_reward_vec(
+			Origin::root(),
+			vec![([1u8; 32], Some(1), 500), ([2u8; 32], Some(1), 300)]
+		));
+		assert_ok!(Crowdloan::initialize_reward_vec(
+			Origin::root(),
+			vec![([3u8; 32], Some(1), 200)]
+		));
+		assert_ok!(Crowdloan::complete_initialization(Origin::root(), 1000));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().total_reward, 1000);
+		roll_to(8);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Balances::free_balance(&1), 1000);
+	});
+}
+
+#[test]
+fn associations_are_indexed_both_ways() {
+	let pairs = get_ed25519_pairs(3);
+	two_assigned_three_unassigned().execute_with(|| {
+		assert_eq!(
+			Crowdloan::claimed_relay_chain_ids(&[1u8; 32]),
+			Some(Some(1))
+		);
+		assert_eq!(Crowdloan::associated_relay_accounts(&1), vec![[1u8; 32]]);
+
+		let relay_account: [u8; 32] = pairs[0].public().into();
+		assert_ok!(Crowdloan::associate_native_identity(
+			Origin::signed(4),
+			3,
+			relay_account,
+			association_signature(&pairs[0], 3)
+		));
+		assert_eq!(
+			Crowdloan::claimed_relay_chain_ids(&relay_account),
+			Some(Some(3))
+		);
+		assert_eq!(
+			Crowdloan::associated_relay_accounts(&3),
+			vec![relay_account]
+		);
+
+		// Moving a reward moves its contributions along
+		assert_ok!(Crowdloan::update_reward_address(Origin::signed(1), 3));
+		assert_eq!(
+			Crowdloan::claimed_relay_chain_ids(&[1u8; 32]),
+			Some(Some(3))
+		);
+		assert_eq!(
+			Crowdloan::associated_relay_accounts(&3),
+			vec![relay_account, [1u8; 32]]
+		);
+		assert!(Crowdloan::associated_relay_accounts(&1).is_empty());
+
+		assert_ok!(Crowdloan::propose_reward_address(Origin::signed(3), 8));
+		assert_ok!(Crowdloan::accept_reward_address(Origin::signed(8), 3));
+		assert_eq!(
+			Crowdloan::claimed_relay_chain_ids(&relay_account),
+			Some(Some(8))
+		);
+		assert_eq!(
+			Crowdloan::claimed_relay_chain_ids(&[1u8; 32]),
+			Some(Some(8))
+		);
+		assert_eq!(
+			Crowdloan::associated_relay_accounts(&8),
+			vec![relay_account, [1u8; 32]]
+		);
+		assert!(Crowdloan::associated_relay_accounts(&3).is_empty());
+	});
+}
+
+#[test]
+fn update_address_to_itself_fails() {
+	two_assigned_three_unassigned().execute_with(|| {
+		assert_noop!(
+			Crowdloan::update_reward_address(Origin::signed(1), 1),
+			Error::<Test>::RewardAddressUnchanged
+		);
+		assert_ok!(Crowdloan::propose_reward_address(Origin::signed(1), 1));
+		assert_noop!(
+			Crowdloan::accept_reward_address(Origin::signed(1), 1),
+			Error::<Test>::RewardAddressUnchanged
+		);
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().total_reward, 500);
+	});
+}
+
+#[test]
+fn two_step_update_address_works() {
+	two_assigned_three_unassigned().execute_with(|| {
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_ok!(Crowdloan::propose_reward_address(Origin::signed(1), 8));
+		assert_eq!(
+			Crowdloan::pending_reward_addresses(&1),
+			Some(PendingRewardAddress {
+				new_reward_account: 8,
+				expires_at: 14
+			})
+		);
+		// Nothing moves until the new account accepts
+		assert!(Crowdloan::accounts_payable(&8).is_none());
+		assert_noop!(
+			Crowdloan::accept_reward_address(Origin::signed(9), 1),
+			Error::<Test>::NoPendingRewardAddress
+		);
+
+		assert_ok!(Crowdloan::accept_reward_address(Origin::signed(8), 1));
+		assert!(Crowdloan::accounts_payable(&1).is_none());
+		assert!(Crowdloan::pending_reward_addresses(&1).is_none());
+		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().claimed_reward, 250);
+		roll_to(6);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(8)));
+		let expected = vec![
+			crate::Event::RewardsPaid(1, 250),
+			crate::Event::RewardAddressProposed(1, 8, 14),
+			crate::Event::RewardAddressUpdated(1, 8),
+			crate::Event::RewardsPaid(8, 125),
+		];
+		assert_eq!(events(), expected);
+	});
+}
+
+#[test]
+fn reward_address_proposals_can_be_cancelled_or_expire() {
+	two_assigned_three_unassigned().execute_with(|| {
+		assert_noop!(
+			Crowdloan::propose_reward_address(Origin::signed(8), 9),
+			Error::<Test>::NoAssociatedClaim
+		);
+		assert_noop!(
+			Crowdloan::cancel_reward_address(Origin::signed(1)),
+			Error::<Test>::NoPendingRewardAddress
+		);
+
+		// Mistyped, then fixed
+		assert_ok!(Crowdloan::propose_reward_address(Origin::signed(1), 9));
+		assert_ok!(Crowdloan::cancel_reward_address(Origin::signed(1)));
+		assert_noop!(
+			Crowdloan::accept_reward_address(Origin::signed(9), 1),
+			Error::<Test>::NoPendingRewardAddress
+		);
+		assert_ok!(Crowdloan::propose_reward_address(Origin::signed(1), 8));
+
+		roll_to(12);
+		assert_noop!(
+			Crowdloan::accept_reward_address(Origin::signed(8), 1),
+			Error::<Test>::RewardAddressProposalExpired
+		);
+		// Proposing again gives the new account more time
+		assert_ok!(Crowdloan::propose_reward_address(Origin::signed(1), 8));
+		assert_ok!(Crowdloan::accept_reward_address(Origin::signed(8), 1));
+		assert_eq!(Crowdloan::accounts_payable(&8).unwrap().total_reward, 500);
+
+		assert_eq!(events()[1], crate::Event::RewardAddressProposalCancelled(1));
+	});
+}
+
+#[test]
+fn merging_rewards_keeps_claims_and_vesting_progress() {
+	for initial_payment in [Perbill::zero(), Perbill::from_percent(20)].iter() {
+		set_initial_payment(*initial_payment);
+		// Merge at every point of the vesting period, after claims made at different points
+		for merge_block in 1u64..10 {
+			two_assigned_three_unassigned().execute_with(|| {
+				roll_to(merge_block / 2 + 1);
+				assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+				roll_to(merge_block);
+				assert_ok!(Crowdloan::show_me_the_money(Origin::signed(2)));
+				let first = Crowdloan::accounts_payable(&1).unwrap();
+				let second = Crowdloan::accounts_payable(&2).unwrap();
+				let claimable = Crowdloan::claimable_for(&1) + Crowdloan::claimable_for(&2);
+
+				assert_ok!(Crowdloan::update_reward_address(Origin::signed(1), 2));
+				let merged = Crowdloan::accounts_payable(&2).unwrap();
+				assert_eq!(merged.total_reward, 1000);
+				assert_eq!(
+					merged.claimed_reward,
+					Balances::free_balance(&1) + Balances::free_balance(&2)
+				);
+				assert!(Crowdloan::claimable_for(&2) >= claimable);
+
+				// From now on, the merged reward vests what both would have vested, give or
+				// take one planck of rounding
+				for block in merge_block..10 {
+					let separately = Crowdloan::vested_reward(&first, block)
+						+ Crowdloan::vested_reward(&second, block);
+					let vested = Crowdloan::vested_reward(&merged, block);
+					assert!(vested >= separately && vested <= separately + 1);
+				}
+
+				// Nothing is lost
+				roll_to(10);
+				assert_ok!(Crowdloan::show_me_the_money(Origin::signed(2)));
+				assert_eq!(
+					Balances::free_balance(&1) + Balances::free_balance(&2),
+					1000
+				);
+				assert_eq!(Crowdloan::claimable_for(&2), 0);
+			});
+		}
+	}
+}
+
+#[test]
+fn paying_comes_from_the_reward_pot() {
+	two_assigned_three_unassigned().execute_with(|| {
+		let issuance = Balances::total_issuance();
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Balances::free_balance(&1), 250);
+		assert_eq!(Balances::free_balance(&Crowdloan::account_id()), 2250);
+		// Nothing was minted
+		assert_eq!(Balances::total_issuance(), issuance);
+	});
+}
+
+#[test]
+fn paying_fails_when_reward_pot_runs_dry() {
+	two_assigned_three_unassigned_with_pot(300).execute_with(|| {
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		roll_to(6);
+		assert_noop!(
+			Crowdloan::show_me_the_money(Origin::signed(1)),
+			Error::<Test>::InsufficientRewardPot
+		);
+		// The failed claim did not change the stored info
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().last_paid, 4u64);
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 250);
+	});
+}
+
+#[test]
+fn initial_payment_is_released_by_first_claim() {
+	set_initial_payment(Perbill::from_percent(20));
+	two_assigned_three_unassigned().execute_with(|| {
+		assert_eq!(
+			Crowdloan::accounts_payable(&1).unwrap().initial_payment,
+			100
+		);
+		// The upfront 100 plus one block worth of the remaining 400
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 150);
+		roll_to(4);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 300);
+		roll_to(8);
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(1)));
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().claimed_reward, 500);
+		let expected = vec![
+			crate::Event::RewardsPaid(1, 150),
+			crate::Event::RewardsPaid(1, 150),
+			crate::Event::RewardsPaid(1, 200),
+		];
+		assert_eq!(events(), expected);
+	});
+}
+
+#[test]
+fn initial_payment_is_released_to_late_associations() {
+	set_initial_payment(Perbill::from_percent(30));
+	let pairs = get_ed25519_pairs(3);
+	two_assigned_three_unassigned().execute_with(|| {
+		let signature = association_signature(&pairs[0], 3);
+		assert_ok!(Crowdloan::associate_native_identity(
+			Origin::signed(4),
+			3,
+			pairs[0].public().into(),
+			signature
+		));
+		roll_to(2);
+		// The upfront 150 plus two blocks worth of the remaining 350
+		assert_ok!(Crowdloan::show_me_the_money(Origin::signed(3)));
+		assert_eq!(Crowdloan::accounts_payable(&3).unwrap().claimed_reward, 237);
+	});
+}
+
+#[test]
+fn fractional_reward_ratio_rounds_down() {
+	genesis(
+		vec![([1u8; 32], 1, 100), ([2u8; 32], 2, 3), ([3u8; 32], 3, 2)],
+		vec![([4u8; 32], 1_000_000)],
+		FixedU128::saturating_from_rational(37, 100),
+		1_000_000,
+	)
+	.execute_with(|| {
+		assert_eq!(Crowdloan::accounts_payable(&1).unwrap().total_reward, 37);
+		// 1.11 and 0.74 lose their dust
+		assert_eq!(Crowdloan::accounts_payable(&2).unwrap().total_reward, 1);
+		assert_eq!(Crowdloan::accounts_payable(&3).unwrap().total_reward, 0);
+		assert_eq!(
+			Crowdloan::unassociated_contributions(&[4u8; 32])
+				.unwrap()
+				.total_reward,
+			370_000
+		);
+	});
+}
+
+#[test]
+fn reward_ratio_dust_is_below_one_planck_per_contributor() {
+	let config = GenesisConfig::<Test> {
+		reward_ratio: FixedU128::saturating_from_rational(37, 100),
+		..Default::default()
+	};
+	let contributions = (1u128..1000).collect::<Vec<_>>();
+	let total_contributed: u128 = contributions.iter().sum();
+	let total_rewarded: u128 = contributions.iter().map(|c| config.reward_for(*c)).sum();
+	// Never pay out more than the exact ratio allows
+	assert!(total_rewarded * 100 <= total_contributed * 37);
+	// And lose less than one planck per contributor
+	assert!(total_contributed * 37 - total_rewarded * 100 < 100 * contributions.len() as u128);
+	for contribution in contributions {
+		let reward = config.reward_for(contribution);
+		assert!(reward * 100 <= contribution * 37);
+		assert!(contribution * 37 < (reward + 1) * 100);
+	}
+}
+
+#[test]
+fn large_contributions_keep_full_precision() {
+	let config = GenesisConfig::<Test> {
+		reward_ratio: FixedU128::saturating_from_rational(37, 100),
+		..Default::default()
+	};
+	// 1000 KSM in planck
+	assert_eq!(
+		config.reward_for(1_000_000_000_000_000),
+		370_000_000_000_000
+	);
+}
+
+#[test]
+fn genesis_accepts_real_relay_chain_amounts() {
+	// 10 KSM in planck, way above u32::MAX
+	genesis(
+		vec![([1u8; 32], 1, 10_000_000_000_000)],
+		vec![],
+		FixedU128::saturating_from_integer(1u128),
+		1,
+	)
+	.execute_with(|| {
+		assert_eq!(
+			Crowdloan::accounts_payable(&1).unwrap().total_reward,
+			10_000_000_000_000
+		);
+	});
+}
+
+#[test]
+#[should_panic(expected = "Duplicate relay chain account")]
+fn genesis_rejects_duplicate_relay_accounts() {
+	genesis(
+		vec![([1u8; 32], 1, 500)],
+		vec![([1u8; 32], 500)],
+		FixedU128::saturating_from_integer(1u128),
+		1,
+	);
+}
+
+#[test]
+#[should_panic(expected = "must be non zero")]
+fn genesis_rejects_zero_contributions() {
+	genesis(
+		vec![([1u8; 32], 1, 0)],
+		vec![],
+		FixedU128::saturating_from_integer(1u128),
+		1,
+	);
+}
+
+#[test]
+fn relay_chain_balance_survives_json_chain_specs() {
+	let amount = RelayChainBalance(123_456_789_000_000_000_000_000);
+	let json = serde_json::to_string(&amount).unwrap();
+	assert_eq!(json, "\"123456789000000000000000\"");
+	assert_eq!(
+		serde_json::from_str::<RelayChainBalance>(&json).unwrap(),
+		amount
+	);
+	// Small amounts written as plain numbers are fine too
+	assert_eq!(
+		serde_json::from_str::<RelayChainBalance>("500").unwrap(),
+		RelayChainBalance(500)
+	);
+	assert!(serde_json::from_str::<RelayChainBalance>("\"-1\"").is_err());
+}
+
+#[test]
+fn reward_info_serializes_for_rpc() {
+	two_assigned_three_unassigned().execute_with(|| {
+		let info = Crowdloan::accounts_payable(&1).unwrap();
+		assert_eq!(
+			serde_json::to_value(&info).unwrap(),
+			serde_json::json!({
+				"totalReward": 500,
+				"claimedReward": 0,
+				"lastPaid": 0,
+				"initialPayment": 0,
+			})
+		);
+	});
+}
+
+#[cfg(feature = "rpc")]
+#[test]
+fn reward_info_rpc_response_serializes_balances_as_hex() {
+	use sp_std::convert::TryFrom;
+	two_assigned_three_unassigned().execute_with(|| {
+		let info = Crowdloan::accounts_payable(&1).unwrap();
+		let response = crate::rpc::RewardInfoResponse::try_from(info).unwrap();
+		assert_eq!(
+			serde_json::to_value(&response).unwrap(),
+			serde_json::json!({
+				"totalReward": "0x1f4",
+				"claimedReward": "0x0",
+				"lastPaid": 0,
+				"initialPayment": "0x0",
+			})
+		);
+	});
+}
+
+parameter_types! {
+	pub const TestCliff: u64 = 4;
+	pub const TestStep: u64 = 3;
+	pub const TestHalfLife: u64 = 2;
+}
+
+#[test]
+fn linear_curve_works() {
+	assert_eq!(<Linear as VestingCurve<u128, u64>>::vested(500, 0, 8), 0);
+	assert_eq!(<Linear as VestingCurve<u128, u64>>::vested(500, 4, 8), 250);
+	assert_eq!(<Linear as VestingCurve<u128, u64>>::vested(500, 8, 8), 500);
+	assert_eq!(<Linear as VestingCurve<u128, u64>>::vested(500, 20, 8), 500);
+}
+
+#[test]
+fn linear_curve_releases_small_rewards_along_the_way() {
+	// Below one planck per block, yet part of it unlocks before the end of the period
+	assert_eq!(<Linear as VestingCurve<u128, u64>>::vested(5, 2, 8), 1);
+	assert_eq!(<Linear as VestingCurve<u128, u64>>::vested(5, 4, 8), 2);
+	assert_eq!(<Linear as VestingCurve<u128, u64>>::vested(5, 7, 8), 4);
+	// The product does not overflow
+	assert_eq!(
+		<Linear as VestingCurve<u128, u64>>::vested(u128::max_value(), 4, 8),
+		u128::max_value() / 2
+	);
+}
+
+#[test]
+fn linear_payments_add_up_to_the_total_reward() {
+	let large_rewards = [
+		1_000_003u128,
+		123_456_789_000_000_000_000,
+		u128::max_value() / 3,
+	];
+	for period in 1..=40u64 {
+		for total in (0..=200u128).chain(large_rewards.iter().cloned()) {
+			let mut claimed = 0u128;
+			for elapsed in 0..=period {
+				let vested = <Linear as VestingCurve<u128, u64>>::vested(total, elapsed, period);
+				// Every block releases its pro-rata share, give or take one planck
+				let payment = vested - claimed;
+				assert!(payment <= total / period as u128 + 1);
+				claimed = vested;
+			}
+			assert_eq!(claimed, total);
+		}
+	}
+}
+
+#[test]
+fn claiming_every_block_pays_the_exact_total() {
+	let rewards = vec![1u128, 3, 7, 500, 1_000_003];
+	genesis(
+		rewards
+			.iter()
+			.enumerate()
+			.map(|(i, reward)| ([i as u8; 32], i as u64 + 1, *reward))
+			.collect(),
+		vec![],
+		FixedU128::saturating_from_integer(1u128),
+		2_000_000,
+	)
+	.execute_with(|| {
+		for block in 1..=8 {
+			roll_to(block);
+			for account in 1..=rewards.len() as u64 {
+				let info = Crowdloan::accounts_payable(&account).unwrap();
+				if info.claimed_reward < info.total_reward {
+					assert_ok!(Crowdloan::show_me_the_money(Origin::signed(account)));
+				}
+			}
+		}
+		for (i, reward) in rewards.iter().enumerate() {
+			assert_eq!(Balances::free_balance(&(i as u64 + 1)), *reward);
+			// No lump at the end
+			let last_payment = events()
+				.into_iter()
+				.filter_map(|event| match event {
+					crate::Event::RewardsPaid(account, amount) if account == i as u64 + 1 => {
+						Some(amount)
+					}
+					_ => None,
+				})
+				.last()
+				.unwrap();
+			assert!(last_payment <= reward / 8 + 1);
+		}
+	});
+}
+
+#[test]
+fn cliff_then_linear_curve_works() {
+	type Curve = CliffThenLinear<TestCliff>;
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 3, 8), 0);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 4, 8), 250);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 5, 8), 312);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 8, 8), 500);
+}
+
+#[test]
+fn stepped_curve_works() {
+	type Curve = Stepped<TestStep>;
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 2, 8), 0);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 3, 8), 187);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 5, 8), 187);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 6, 8), 375);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 7, 8), 375);
+	// The end of the period is not a step boundary, the rest unlocks there anyway
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(500, 8, 8), 500);
+}
+
+#[test]
+fn exponential_decay_curve_works() {
+	type Curve = ExponentialDecay<TestHalfLife>;
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(1000, 0, 8), 0);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(1000, 1, 8), 250);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(1000, 2, 8), 500);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(1000, 4, 8), 750);
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(1000, 6, 8), 875);
+	// The tail unlocks at the end of the period
+	assert_eq!(<Curve as VestingCurve<u128, u64>>::vested(1000, 8, 8), 1000);
+}
+
+#[test]
+fn vesting_curves_are_monotonic() {
+	fn check<C: VestingCurve<u128, u64>>() {
+		let mut previous = 0;
+		for elapsed in 0..=10 {
+			let vested = C::vested(1_000_003, elapsed, 8);
+			assert!(vested >= previous);
+			previous = vested;
+		}
+		assert_eq!(previous, 1_000_003);
+	}
+	check::<Linear>();
+	check::<CliffThenLinear<TestCliff>>();
+	check::<Stepped<TestStep>>();
+	check::<ExponentialDecay<TestHalfLife>>();
+}
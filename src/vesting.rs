@@ -0,0 +1,181 @@
+// Copyright 2019-2021 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Vesting curves
+//!
+//! A vesting curve answers a single question: how much of a reward is vested once a number of
+//! blocks of the vesting period have elapsed. The pallet pays out the difference between that and
+//! whatever has already been claimed, so curves only need to be monotonic and reach the full
+//! reward at the end of the period.
+//!
+//! Alternatively, in `PayoutMode::Lock`, the pallet hands out the whole reward on the first payout
+//! and locks what is not vested yet with the runtime's `VestingSchedule`, eg `pallet_vesting`.
+
+use frame_support::dispatch::{DispatchError, DispatchResult};
+use frame_support::traits::{Currency, Get, VestingSchedule};
+use sp_runtime::helpers_128bit::multiply_by_rational;
+use sp_runtime::traits::{AtLeast32BitUnsigned, Saturating, Zero};
+use sp_runtime::{Perbill, RuntimeDebug, SaturatedConversion};
+use sp_std::marker::PhantomData;
+
+/// Describes how a reward unlocks over the vesting period.
+pub trait VestingCurve<Balance, BlockNumber> {
+	/// How much of `total_reward` is vested once `elapsed` blocks of a `period` blocks long
+	/// vesting period have passed.
+	///
+	/// Implementations must never decrease as `elapsed` grows and must return `total_reward` once
+	/// `elapsed` reaches `period`.
+	fn vested(total_reward: Balance, elapsed: BlockNumber, period: BlockNumber) -> Balance;
+}
+
+/// The reward unlocks linearly: `total_reward * elapsed / period`, rounded down.
+///
+/// The product is computed at full precision before dividing, so every claim releases its pro-rata
+/// share and rounding never accumulates into a lump at the end of the period. Rewards smaller than
+/// the period still unlock along the way rather than all at the end.
+pub struct Linear;
+
+impl<Balance, BlockNumber> VestingCurve<Balance, BlockNumber> for Linear
+where
+	Balance: AtLeast32BitUnsigned + Copy,
+	BlockNumber: AtLeast32BitUnsigned + Copy,
+{
+	fn vested(total_reward: Balance, elapsed: BlockNumber, period: BlockNumber) -> Balance {
+		if elapsed >= period {
+			return total_reward;
+		}
+		// Cannot fail: the period is non zero, and the result is below `total_reward`
+		multiply_by_rational(
+			total_reward.saturated_into::<u128>(),
+			elapsed.saturated_into::<u128>(),
+			period.saturated_into::<u128>(),
+		)
+		.map(|vested| vested.saturated_into::<Balance>())
+		.unwrap_or(total_reward)
+	}
+}
+
+/// Nothing unlocks until `Cliff` blocks have elapsed. At that point everything that would have
+/// vested linearly so far unlocks at once, and the rest keeps vesting linearly.
+pub struct CliffThenLinear<Cliff>(PhantomData<Cliff>);
+
+impl<Balance, BlockNumber, Cliff> VestingCurve<Balance, BlockNumber> for CliffThenLinear<Cliff>
+where
+	Balance: AtLeast32BitUnsigned + Copy,
+	BlockNumber: AtLeast32BitUnsigned + Copy,
+	Cliff: Get<BlockNumber>,
+{
+	fn vested(total_reward: Balance, elapsed: BlockNumber, period: BlockNumber) -> Balance {
+		if elapsed < Cliff::get() && elapsed < period {
+			return Zero::zero();
+		}
+		Linear::vested(total_reward, elapsed, period)
+	}
+}
+
+/// The reward unlocks in equal tranches, one every `Step` blocks (eg monthly). Whatever is left
+/// when the period ends unlocks with the last tranche.
+pub struct Stepped<Step>(PhantomData<Step>);
+
+impl<Balance, BlockNumber, Step> VestingCurve<Balance, BlockNumber> for Stepped<Step>
+where
+	Balance: AtLeast32BitUnsigned + Copy,
+	BlockNumber: AtLeast32BitUnsigned + Copy,
+	Step: Get<BlockNumber>,
+{
+	fn vested(total_reward: Balance, elapsed: BlockNumber, period: BlockNumber) -> Balance {
+		if elapsed >= period {
+			return total_reward;
+		}
+		let step = Step::get();
+		if step.is_zero() {
+			return Linear::vested(total_reward, elapsed, period);
+		}
+		let completed_steps = elapsed / step;
+		Linear::vested(total_reward, completed_steps.saturating_mul(step), period)
+	}
+}
+
+/// The unlock rate halves every `HalfLife` blocks, so most of the reward unlocks early and the
+/// tail trickles out slowly. Within a half life the reward unlocks linearly. Whatever is still
+/// locked when the period ends unlocks then, so `HalfLife` should be well below the vesting period.
+pub struct ExponentialDecay<HalfLife>(PhantomData<HalfLife>);
+
+impl<Balance, BlockNumber, HalfLife> VestingCurve<Balance, BlockNumber>
+	for ExponentialDecay<HalfLife>
+where
+	Balance: AtLeast32BitUnsigned + Copy,
+	BlockNumber: AtLeast32BitUnsigned + Copy,
+	HalfLife: Get<BlockNumber>,
+{
+	fn vested(total_reward: Balance, elapsed: BlockNumber, period: BlockNumber) -> Balance {
+		let half_life = HalfLife::get();
+		if elapsed >= period || half_life.is_zero() {
+			return total_reward;
+		}
+		let half = Perbill::from_percent(50);
+		let halvings = (elapsed / half_life).saturated_into::<u32>();
+		let locked_at_halving = half.saturating_pow(halvings as usize);
+		let progress = Perbill::from_rational_approximation(
+			(elapsed % half_life).saturated_into::<u128>(),
+			half_life.saturated_into::<u128>(),
+		);
+		let locked = locked_at_halving.saturating_sub(locked_at_halving * half * progress);
+		total_reward.saturating_sub(locked.mul_ceil(total_reward))
+	}
+}
+
+/// How vested rewards reach contributors.
+#[derive(Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum PayoutMode {
+	/// Contributors claim what is vested as often as they like, and the pallet tracks what was
+	/// claimed. Rewards follow the `VestingCurve`.
+	Claim,
+	/// The first payout transfers the whole reward, and whatever is not vested yet is locked with
+	/// the `VestingSchedule`. Contributors see their full balance and can vote or stake with it.
+	/// The lock is released linearly until the end of the vesting period, whatever the
+	/// `VestingCurve`. Contributions associated after the first payout are paid and locked the
+	/// same way by the next one, merging the locks into a single schedule. Do not switch a chain
+	/// that already paid rewards in `Claim` mode to this mode.
+	Lock,
+}
+
+/// A `VestingSchedule` for runtimes without one, which only use `PayoutMode::Claim`. Creating a
+/// schedule always fails.
+pub struct NoVestingSchedule<Currency, BlockNumber>(PhantomData<(Currency, BlockNumber)>);
+
+impl<AccountId, C, BlockNumber> VestingSchedule<AccountId> for NoVestingSchedule<C, BlockNumber>
+where
+	C: Currency<AccountId>,
+{
+	type Moment = BlockNumber;
+	type Currency = C;
+
+	fn vesting_balance(_who: &AccountId) -> Option<C::Balance> {
+		None
+	}
+
+	fn add_vesting_schedule(
+		_who: &AccountId,
+		_locked: C::Balance,
+		_per_block: C::Balance,
+		_starting_block: BlockNumber,
+	) -> DispatchResult {
+		Err(DispatchError::Other("No vesting schedule configured"))
+	}
+
+	fn remove_vesting_schedule(_who: &AccountId) {}
+}